@@ -0,0 +1,1172 @@
+//! The command-line arguments of o-o: the options, the three fds, and the command lines split by the pipe and separators.
+
+use std::borrow::Cow;
+use std::time::Duration;
+
+use ng_clp::{is_argument, is_flag_or_option, next_index, parse, unwrap_argument};
+
+use crate::*;
+
+fn unpack_shorthand_args(a: &str) -> Option<Vec<&'static str>> {
+    if a.len() != 3 && a.len() != 2 {
+        return None;
+    }
+    if a == "--" { // the separator takes precedence over the two-fd shorthand (both mean `- - -` anyway)
+        return None;
+    }
+
+    let mut v: Vec<&'static str> = vec![];
+    for c in a.chars() {
+        if c == '-' {
+            v.push("-");
+        } else if c == '.' {
+            v.push(".");
+        } else if c == '=' {
+            v.push("=");
+        } else {
+            return None;
+        }
+    }
+    while v.len() < 3 {
+        v.push("-");
+    }
+
+    Some(v)
+}
+
+/// Splits a bundle of short flags, GNU-style, e.g., `-Fk` into `-F -k`. The last one of a bundle may be a short option
+/// taking a value, which is the rest of the bundle if any, e.g., `-Fd/tmp` into `-F -d /tmp`. A token not starting with
+/// a flag, e.g., a short option with its value attached (`-pP`), or including a letter of no short option, is not split.
+fn unbundle_short_flags(token: &str) -> Option<Vec<&str>> {
+    let short_flag = |c| match c {
+        'F' => Some("-F"),
+        'k' => Some("-k"),
+        'h' => Some("-h"),
+        'V' => Some("-V"),
+        _ => None,
+    };
+    let short_option = |c| match c {
+        'd' => Some("-d"),
+        'e' => Some("-e"),
+        'p' => Some("-p"),
+        's' => Some("-s"),
+        't' => Some("-t"),
+        'u' => Some("-u"),
+        _ => None,
+    };
+
+    let letters = token.strip_prefix('-').filter(|l| l.len() >= 2 && !l.starts_with('-'))?;
+    short_flag(letters.chars().next()?)?;
+    let mut split = vec![];
+    for (i, c) in letters.char_indices() {
+        if let Some(flag) = short_flag(c) {
+            split.push(flag);
+        } else if let Some(option) = short_option(c) {
+            split.push(option);
+            let value = &letters[i + 1..];
+            if !value.is_empty() {
+                split.push(value);
+            }
+            break;
+        } else {
+            return None;
+        }
+    }
+    Some(split)
+}
+
+/// Splits a string into words as in shell: words are separated by whitespace, and
+/// single quotes, double quotes and backslashes work as quoting.
+/// `origin` names where the string comes from, in error messages.
+pub fn split_shell_words(s: &str, origin: &str) -> Result<Vec<String>, OOError> {
+    let mut words = vec![];
+    let mut word: Option<String> = None;
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                words.extend(word.take());
+            }
+            '\'' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => w.push(c),
+                        None => return Err(OOError::CLIError { message: format!("unterminated quote in {}: {}", origin, s) }),
+                    }
+                }
+            }
+            '"' => {
+                let w = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) if c == '"' || c == '\\' => w.push(c),
+                            Some(c) => {
+                                w.push('\\');
+                                w.push(c);
+                            }
+                            None => return Err(OOError::CLIError { message: format!("unterminated quote in {}: {}", origin, s) }),
+                        },
+                        Some(c) => w.push(c),
+                        None => return Err(OOError::CLIError { message: format!("unterminated quote in {}: {}", origin, s) }),
+                    }
+                }
+            }
+            '\\' => {
+                let w = word.get_or_insert_with(String::new);
+                if let Some(c) = chars.next() {
+                    w.push(c);
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Which exit status of the failed command lines is reported with --keep-going.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum KeepGoingExit {
+    #[default]
+    First,
+    Last,
+    Max,
+}
+
+impl KeepGoingExit {
+    /// Returns the exit status reported for the exit statuses of the command lines, which is 0 when all of them succeeded.
+    pub fn exit_code(self, exit_codes: &[i32]) -> i32 {
+        let mut failures = exit_codes.iter().copied().filter(|&c| c != 0);
+        match self {
+            KeepGoingExit::First => failures.next(),
+            KeepGoingExit::Last => failures.next_back(),
+            KeepGoingExit::Max => failures.max(),
+        }.unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args<'s> {
+    pub help: bool,
+    pub version: bool,
+    pub fds: Vec<&'s str>,
+    pub fd_options: [Option<&'s str>; 3],
+    pub command_line: Vec<&'s str>,
+    pub force_overwrite: bool,
+    /// The variables of -e and -u, in the order given. The value of `-e VAR` is read from the environment of o-o.
+    pub envs: Vec<(&'s str, Option<Cow<'s, str>>)>,
+    pub env_files: Vec<&'s str>,
+    pub clear_env: bool,
+    pub env_passthrough: Vec<&'s str>,
+    pub working_directory: Option<&'s str>,
+    pub chdir_to_input: bool,
+    pub chroot: Option<&'s str>,
+    pub argv0: Option<&'s str>,
+    pub inherited_fds: [Option<i32>; 3],
+    pub keep_going: bool,
+    pub keep_going_exit: KeepGoingExit,
+    pub max_parallel: Option<usize>,
+    pub spawn_limit: Option<usize>,
+    pub deadline: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+    pub lock_file: Option<&'s str>,
+    pub lock_timeout: Option<Duration>,
+    pub retries: usize,
+    pub retry_delay: Option<Duration>,
+    pub retry_jitter: Option<Duration>,
+    pub report_json: Option<Option<&'s str>>,
+    pub trace_file: Option<&'s str>,
+    pub exit_code_file: Option<&'s str>,
+    pub no_sync: bool,
+    pub ensure_trailing_newline: bool,
+    pub atomic_stdout: bool,
+    pub report_lines: bool,
+    pub abort_on_stderr: bool,
+    pub fail_on_empty_output: bool,
+    pub touch_output: bool,
+    pub progress: bool,
+    pub chmod: Option<u32>,
+    pub publish_as: Option<&'s str>,
+    pub umask: Option<u32>,
+    pub temp_prefix: Option<&'s str>,
+    pub temp_suffix: Option<&'s str>,
+    pub output_template: Option<&'s str>,
+    pub skip_if_fresh: bool,
+    pub append_header: Option<&'s str>,
+    pub dump_config: bool,
+    pub dry_run: bool,
+    pub timeout_signal: Option<i32>,
+    pub stdout_hash: Option<HashAlgorithm>,
+    pub stdin_json: Option<&'s str>,
+    pub stdin_tee: Option<&'s str>,
+    pub tee: bool,
+    pub required_commands: Vec<&'s str>,
+    pub capture: Option<(&'s str, &'s str)>,
+    pub setup_command: Option<Vec<String>>,
+    pub teardown_command: Option<Vec<String>>,
+    pub explain: bool,
+    pub allow_empty_segments: bool,
+    pub comment: Option<&'s str>,
+    pub commands_file: Option<&'s str>,
+    pub args0_file: Option<&'s str>,
+    pub convert: Option<LineEnding>,
+    pub debug_info: bool,
+    pub debug_info0: bool,
+    pub pipe_str: Option<&'s str>,
+    pub separator_str: Option<&'s str>,
+    pub and_separator_str: Option<&'s str>,
+    pub or_separator_str: Option<&'s str>,
+    pub token_regex: bool,
+    pub tempdir_placeholder: Option<&'s str>,
+    pub interleave: bool,
+    pub stream_labels: Option<(&'s str, &'s str)>,
+    pub prefix_timestamps: Option<&'s str>,
+    pub glob: bool,
+    pub pty: bool,
+    pub force_color: bool,
+    pub strip_ansi: bool,
+    pub strip_ansi_stderr: bool,
+    pub line_ending: Option<LineEnding>,
+    pub strip_bom: bool,
+    pub dereference_stdin: bool,
+    pub stdin_null: bool,
+    pub no_inherit_stdin: bool,
+    pub null_io: bool,
+    pub stderr_on_error: Option<usize>,
+    pub stdout_on_error: Option<usize>,
+}
+
+impl Args<'_> {
+    pub fn parse<'s>(argv: &[&'s str]) -> anyhow::Result<Args<'s>> {
+        let args = Args::parse_unchecked(argv)?;
+        if args.help || args.version {
+            return Ok(args);
+        }
+
+        if args.convert.is_some() {
+            if !args.command_line.is_empty() || args.commands_file.is_some() || args.args0_file.is_some() {
+                return Err(OOError::CLIError { message: "option --convert cannot be used with a command line".to_string() }.into())
+            }
+        } else if args.commands_file.is_some() {
+            if !args.command_line.is_empty() || args.args0_file.is_some() {
+                return Err(OOError::CLIError { message: "option --commands-file cannot be used with a command line".to_string() }.into())
+            }
+        } else if args.command_line.is_empty() && args.args0_file.is_none() && !args.dump_config {
+            return Err(OOError::CLIError { message: "no command line specified".to_string() }.into())
+        }
+
+        Ok(args)
+    }
+
+    /// Parses the arguments as `parse`, but without checking whether a command line is given.
+    pub fn parse_unchecked<'s>(argv: &[&'s str]) -> anyhow::Result<Args<'s>> {
+        let mut args = Args::default();
+
+        let mut argv: Vec<&'s str> = argv[1..].to_vec();
+        let mut argv_index = 0;
+        let mut fds_closed = false;
+        // Options are parsed until the three fds are collected (as positionals or by --stdin/--stdout/--stderr). After
+        // that, only the options o-o recognizes are still taken, up to the first argument, `--`, or unknown flag, which
+        // starts the command line; the rest is the command line, verbatim
+        while argv_index < argv.len() {
+            let fds_filled = args.fds.len() + args.fd_options.iter().flatten().count() >= 3;
+            if fds_filled && !is_flag_or_option(argv[argv_index]) && argv[argv_index] != "--" {
+                break;
+            }
+            if args.fds.is_empty() {
+                if let Some(u) = unpack_shorthand_args(argv[argv_index]) {
+                    args.fds = u;
+                    argv_index += 1;
+                    continue;
+                }
+            }
+            if let Some(flags) = unbundle_short_flags(argv[argv_index]) {
+                argv.splice(argv_index..argv_index + 1, flags);
+            }
+            let pr = parse(&argv, argv_index)?;
+            let eat = match pr.0 {
+                "-h" | "--help" => { // the rest is not parsed, as the help is all to be shown
+                    args.help = true;
+                    return Ok(args);
+                }
+                "-V" | "--version" => {
+                    args.version = true;
+                    return Ok(args);
+                }
+                "-F" | "--force-overwrite" => {
+                    args.force_overwrite = true;
+                    1
+                }
+                "-k" | "--keep-going" => {
+                    args.keep_going = true;
+                    1
+                }
+                "--interleave" => {
+                    args.interleave = true;
+                    if argv[argv_index].contains('=') {
+                        if unwrap_argument(pr)? != "prefix" {
+                            return Err(OOError::CLIError { message: format!("option --interleave's argument should be `prefix`: {}", argv[argv_index]) }.into());
+                        }
+                        args.stream_labels = Some(("out", "err"));
+                        2
+                    } else {
+                        1
+                    }
+                }
+                "--label" => {
+                    if argv[argv_index].contains('=') {
+                        let value = unwrap_argument(pr)?;
+                        match value.split_once(',') {
+                            Some((o, e)) if !e.contains(',') => { args.stream_labels = Some((o, e)); }
+                            _ => {
+                                return Err(OOError::CLIError { message: format!("option --label's argument should be `OUT,ERR`: {}", value) }.into());
+                            }
+                        }
+                        2
+                    } else {
+                        args.stream_labels = Some(("1", "2"));
+                        1
+                    }
+                }
+                "--prefix-timestamps" => {
+                    if argv[argv_index].contains('=') {
+                        let format = unwrap_argument(pr)?;
+                        if !is_valid_timestamp_format(format) {
+                            return Err(OOError::CLIError { message: format!("invalid timestamp format: {}", format) }.into());
+                        }
+                        args.prefix_timestamps = Some(format);
+                        2
+                    } else {
+                        args.prefix_timestamps = Some(DEFAULT_TIMESTAMP_FORMAT);
+                        1
+                    }
+                }
+                "--glob" => {
+                    args.glob = true;
+                    1
+                }
+                "--pty" => {
+                    args.pty = true;
+                    1
+                }
+                "--force-color" => {
+                    args.force_color = true;
+                    1
+                }
+                "--strip-ansi" => {
+                    args.strip_ansi = true;
+                    if argv[argv_index].contains('=') {
+                        if unwrap_argument(pr)? != "both" {
+                            return Err(OOError::CLIError { message: format!("option --strip-ansi's argument should be `both`: {}", argv[argv_index]) }.into());
+                        }
+                        args.strip_ansi_stderr = true;
+                        2
+                    } else {
+                        1
+                    }
+                }
+                "--lf" | "--crlf" => {
+                    let line_ending = if pr.0 == "--lf" { LineEnding::Lf } else { LineEnding::Crlf };
+                    if args.line_ending.is_some_and(|le| le != line_ending) {
+                        return Err(OOError::CLIError { message: "options --lf and --crlf are mutually exclusive".to_string() }.into());
+                    }
+                    args.line_ending = Some(line_ending);
+                    1
+                }
+                "--stdin-null" => {
+                    args.stdin_null = true;
+                    1
+                }
+                "--no-inherit-stdin" => {
+                    args.no_inherit_stdin = true;
+                    1
+                }
+                "--null-io" => {
+                    args.null_io = true;
+                    1
+                }
+                "--strip-bom" => {
+                    args.strip_bom = true;
+                    1
+                }
+                "--dereference-stdin" => {
+                    args.dereference_stdin = true;
+                    1
+                }
+                "--stdout-on-error" | "--stderr-on-error" => {
+                    let size = if argv[argv_index].contains('=') {
+                        let value = unwrap_argument(pr)?;
+                        match value.parse::<usize>() {
+                            Ok(size) if size > 0 => size,
+                            _ => {
+                                return Err(OOError::CLIError { message: format!("option {}'s argument should be a positive number of bytes: {}", pr.0, value) }.into());
+                            }
+                        }
+                    } else {
+                        DEFAULT_ON_ERROR_BUFFER_SIZE
+                    };
+                    if pr.0 == "--stdout-on-error" {
+                        args.stdout_on_error = Some(size);
+                    } else {
+                        args.stderr_on_error = Some(size);
+                    }
+                    if argv[argv_index].contains('=') { 2 } else { 1 }
+                }
+                "--keep-going-exit" => {
+                    let value = unwrap_argument(pr)?;
+                    args.keep_going_exit = match value {
+                        "first" => KeepGoingExit::First,
+                        "last" => KeepGoingExit::Last,
+                        "max" => KeepGoingExit::Max,
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --keep-going-exit's argument should be one of `first`, `last` or `max`: {}", value) }.into());
+                        }
+                    };
+                    2
+                }
+                "--max-parallel" => {
+                    let value = unwrap_argument(pr)?;
+                    match value.parse::<usize>() {
+                        Ok(n) if n > 0 => { args.max_parallel = Some(n); }
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --max-parallel's argument should be a positive number: {}", value) }.into());
+                        }
+                    }
+                    2
+                }
+                "--spawn-limit" => {
+                    let value = unwrap_argument(pr)?;
+                    match value.parse::<usize>() {
+                        Ok(n) if n > 0 => { args.spawn_limit = Some(n); }
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --spawn-limit's argument should be a positive number: {}", value) }.into());
+                        }
+                    }
+                    2
+                }
+                "--lock-file" => {
+                    args.lock_file = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--lock-timeout" => {
+                    let value = unwrap_argument(pr)?;
+                    match value.parse::<f64>() {
+                        Ok(secs) if secs >= 0.0 && secs.is_finite() => { args.lock_timeout = Some(Duration::from_secs_f64(secs)); }
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --lock-timeout's argument should be a number of seconds: {}", value) }.into());
+                        }
+                    }
+                    2
+                }
+                "--deadline" | "--idle-timeout" => {
+                    let value = unwrap_argument(pr)?;
+                    let secs = match value.parse::<f64>() {
+                        Ok(secs) if secs > 0.0 && secs.is_finite() => Duration::from_secs_f64(secs),
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option {}'s argument should be a positive number of seconds: {}", pr.0, value) }.into());
+                        }
+                    };
+                    if pr.0 == "--deadline" {
+                        args.deadline = Some(secs);
+                    } else {
+                        args.idle_timeout = Some(secs);
+                    }
+                    2
+                }
+                "--stdin-json" => {
+                    args.stdin_json = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--stdin-tee" => {
+                    args.stdin_tee = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--tee" => {
+                    args.tee = true;
+                    1
+                }
+                "--stdout-hash" => {
+                    let value = unwrap_argument(pr)?;
+                    args.stdout_hash = Some(match value {
+                        "sha256" => HashAlgorithm::Sha256,
+                        "md5" => HashAlgorithm::Md5,
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --stdout-hash's argument should be either `sha256` or `md5`: {}", value) }.into());
+                        }
+                    });
+                    2
+                }
+                "--signal-on-timeout" => {
+                    let value = unwrap_argument(pr)?;
+                    let signal = parse_signal(value);
+                    if signal.is_none() {
+                        return Err(OOError::CLIError { message: format!("option --signal-on-timeout's argument should be a signal name or number: {}", value) }.into());
+                    }
+                    args.timeout_signal = signal;
+                    2
+                }
+                "--retries" => {
+                    let value = unwrap_argument(pr)?;
+                    args.retries = value.parse::<usize>().map_err(|_| OOError::CLIError { message: format!("option --retries's argument should be a number: {}", value) })?;
+                    2
+                }
+                "--retry-delay" | "--retry-jitter" => {
+                    let value = unwrap_argument(pr)?;
+                    let ms = value.parse::<u64>().map_err(|_| OOError::CLIError { message: format!("option {}'s argument should be a number of milliseconds: {}", pr.0, value) })?;
+                    if pr.0 == "--retry-delay" {
+                        args.retry_delay = Some(Duration::from_millis(ms));
+                    } else {
+                        args.retry_jitter = Some(Duration::from_millis(ms));
+                    }
+                    2
+                }
+                "--report-json" => {
+                    if argv[argv_index].contains('=') {
+                        args.report_json = Some(Some(unwrap_argument(pr)?));
+                        2
+                    } else {
+                        args.report_json = Some(None);
+                        1
+                    }
+                }
+                "--trace-file" => {
+                    args.trace_file = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--skip-if-fresh" => {
+                    args.skip_if_fresh = true;
+                    1
+                }
+                "--exit-code-file" => {
+                    args.exit_code_file = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--no-sync" => {
+                    args.no_sync = true;
+                    1
+                }
+                "--ensure-trailing-newline" => {
+                    args.ensure_trailing_newline = true;
+                    1
+                }
+                "--atomic-stdout" => {
+                    args.atomic_stdout = true;
+                    1
+                }
+                "--report-lines" => {
+                    args.report_lines = true;
+                    1
+                }
+                "--fail-on-empty-output" => {
+                    args.fail_on_empty_output = true;
+                    1
+                }
+                "--abort-on-stderr" => {
+                    args.abort_on_stderr = true;
+                    1
+                }
+                "--progress" => {
+                    args.progress = true;
+                    1
+                }
+                "--touch-output" => {
+                    args.touch_output = true;
+                    1
+                }
+                "--publish-as" => {
+                    args.publish_as = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--chmod" => {
+                    args.chmod = Some(parse_octal_mode(unwrap_argument(pr)?)?);
+                    2
+                }
+                "--umask" => {
+                    let value = unwrap_argument(pr)?;
+                    let mask = parse_octal_mode(value)?;
+                    if mask > 0o777 {
+                        return Err(OOError::CLIError { message: format!("option --umask's argument should be up to 777: {}", value) }.into());
+                    }
+                    args.umask = Some(mask);
+                    2
+                }
+                "--append-header" => {
+                    args.append_header = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--explain" => {
+                    args.explain = true;
+                    1
+                }
+                "--dry-run" => {
+                    args.dry_run = true;
+                    1
+                }
+                "--require" => {
+                    args.required_commands.push(unwrap_argument(pr)?);
+                    2
+                }
+                "--capture" => {
+                    let value = unwrap_argument(pr)?;
+                    match value.split_once(':') {
+                        Some((name, file)) if is_env_name(name) && !file.is_empty() => { args.capture = Some((name, file)); }
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --capture's argument should be `NAME:FILE`: {}", value) }.into());
+                        }
+                    }
+                    2
+                }
+                "--setup" | "--teardown" => {
+                    let value = unwrap_argument(pr)?;
+                    let command = split_shell_words(value, pr.0)?;
+                    if command.is_empty() {
+                        return Err(OOError::CLIError { message: format!("option {} requires a command", pr.0) }.into());
+                    }
+                    if pr.0 == "--setup" { &mut args.setup_command } else { &mut args.teardown_command }.replace(command);
+                    2
+                }
+                "--dump-config" => {
+                    args.dump_config = true;
+                    1
+                }
+                "--allow-empty-segments" => {
+                    args.allow_empty_segments = true;
+                    1
+                }
+                "--comment" => {
+                    if argv[argv_index].contains('=') {
+                        let marker = unwrap_argument(pr)?;
+                        if marker.is_empty() {
+                            return Err(OOError::CLIError { message: "option --comment's argument should not be empty".to_string() }.into());
+                        }
+                        args.comment = Some(marker);
+                        2
+                    } else {
+                        args.comment = Some("#");
+                        1
+                    }
+                }
+                "--commands-file" => {
+                    args.commands_file = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--args0-file" => {
+                    args.args0_file = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--convert" => {
+                    let value = unwrap_argument(pr)?;
+                    args.convert = Some(match value {
+                        "dos2unix" => LineEnding::Lf,
+                        "unix2dos" => LineEnding::Crlf,
+                        _ => {
+                            return Err(OOError::CLIError { message: format!("option --convert's argument should be either `dos2unix` or `unix2dos`: {}", value) }.into());
+                        }
+                    });
+                    2
+                }
+                "--debug-info" => {
+                    args.debug_info = true;
+                    1
+                }
+                "--debug-info0" => { // same as --debug-info, but tempdir-including arguments are NUL-separated
+                    args.debug_info = true;
+                    args.debug_info0 = true;
+                    1
+                }
+                "-e" => {
+                    let value = unwrap_argument(pr)?;
+                    match value.find('=') {
+                        Some(p) => args.envs.push((&value[..p], Some(Cow::Borrowed(&value[p + 1..])))),
+                        None if value.is_empty() => {
+                            return Err(OOError::CLIError { message: format!("option -e's argument should be `VAR=VALUE` or `VAR`: {}", pr.0) }.into());
+                        }
+                        // The value in the environment of o-o, or the variable is left absent as by -u, so the later option wins
+                        None => args.envs.push((value, std::env::var(value).ok().map(Cow::Owned))),
+                    }
+                    2
+                }
+                "--env-file" => {
+                    args.env_files.push(unwrap_argument(pr)?);
+                    2
+                }
+                "--clear-env" => {
+                    args.clear_env = true;
+                    1
+                }
+                "--env-passthrough" => {
+                    let name = unwrap_argument(pr)?;
+                    if name.is_empty() || name.contains('=') {
+                        return Err(OOError::CLIError { message: format!("option --env-passthrough's argument should be a variable name: {}", name) }.into());
+                    }
+                    args.env_passthrough.push(name);
+                    2
+                }
+                "-u" | "--unset" => {
+                    let name = unwrap_argument(pr)?;
+                    if name.is_empty() || name.contains('=') {
+                        return Err(OOError::CLIError { message: format!("option -u's argument should be a variable name: {}", name) }.into());
+                    }
+                    args.envs.push((name, None));
+                    2
+                }
+                "-d" | "--working-directory" => {
+                    args.working_directory = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--chdir-to-input" => {
+                    args.chdir_to_input = true;
+                    1
+                }
+                "--stdin-fd" | "--stdout-fd" | "--stderr-fd" => {
+                    let value = unwrap_argument(pr)?;
+                    let fd = value.parse::<i32>().ok().filter(|&n| n >= 0).ok_or_else(|| OOError::CLIError { message: format!("option {}'s argument should be a descriptor number: {}", pr.0, value) })?;
+                    let i = ["--stdin-fd", "--stdout-fd", "--stderr-fd"].iter().position(|&o| o == pr.0).unwrap();
+                    args.inherited_fds[i] = Some(fd);
+                    2
+                }
+                "--argv0" => {
+                    args.argv0 = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--chroot" => {
+                    args.chroot = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "-p" | "--pipe"  => {
+                    args.pipe_str = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--token-regex" => {
+                    args.token_regex = true;
+                    1
+                }
+                "-s" | "--separator"  => {
+                    args.separator_str = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--and-separator" => {
+                    args.and_separator_str = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--or-separator" => {
+                    args.or_separator_str = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "-t" | "--tempdir-placeholder" => {
+                    args.tempdir_placeholder = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--temp-prefix" => {
+                    args.temp_prefix = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--temp-suffix" => {
+                    args.temp_suffix = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--output-template" => {
+                    let template = unwrap_argument(pr)?;
+                    if !template.contains(OUTPUT_TEMPLATE_INDEX) {
+                        return Err(OOError::CLIError { message: format!("option --output-template's argument should contain `{}`: {}", OUTPUT_TEMPLATE_INDEX, template) }.into());
+                    }
+                    args.output_template = Some(template);
+                    2
+                }
+                "--stdin" | "--stdout" | "--stderr" => {
+                    let i = ["--stdin", "--stdout", "--stderr"].iter().position(|&o| o == pr.0).unwrap();
+                    args.fd_options[i] = Some(unwrap_argument(pr)?);
+                    2
+                }
+                "--" => { // separator
+                    fds_closed = true;
+                    break;
+                }
+                a if is_argument(a) => { // argument
+                    args.fds.push(a);
+                    1
+                }
+                _ => 0 // unknown flag/option 
+            };
+            if eat == 0 && fds_filled { // not an o-o option, so the command starts here
+                break;
+            }
+
+            argv_index = next_index(&argv, argv_index, eat)?;
+            if argv_index >= argv.len() {
+                break;
+            }
+        }
+        if argv_index < argv.len() {
+            if argv[argv_index] == "--" { // in case a redundant "--" is given as the 4th argument
+                argv_index += 1;
+            }
+            args.command_line.extend_from_slice(&argv[argv_index..]);
+        }
+
+        if fds_closed || args.fd_options.iter().any(Option::is_some) {
+            args.fds = Args::merge_fds(&args.fds, &args.fd_options)?;
+        }
+
+        if args.null_io {
+            args.apply_null_io()?;
+        }
+
+        Ok(args)
+    }
+
+    /// Puts the positional fds and the ones given by --stdin/--stdout/--stderr together. A positional fd takes the slot of
+    /// its position, so giving an fd both ways is an error. The fds given neither way are `-`.
+    fn merge_fds<'s>(positionals: &[&'s str], fd_options: &[Option<&'s str>; 3]) -> Result<Vec<&'s str>, OOError> {
+        let mut fds = vec![];
+        for (i, (name, option)) in ["stdin", "stdout", "stderr"].iter().zip(fd_options).enumerate() {
+            fds.push(match (positionals.get(i), option) {
+                (Some(p), Some(o)) => {
+                    return Err(OOError::CLIError { message: format!("{} is given both as an argument and by option --{}: {} and {}", name, name, p, o) });
+                }
+                (Some(p), None) => p,
+                (None, Some(o)) => o,
+                (None, None) => "-",
+            });
+        }
+        Ok(fds)
+    }
+
+    /// Makes stdin empty and discards stdout and stderr (option --null-io), i.e. `. . .` if `.` were allowed for stdin.
+    /// The fds given explicitly have to agree with it.
+    fn apply_null_io(&mut self) -> Result<(), OOError> {
+        if self.fds.is_empty() {
+            self.fds = vec!["-", "-", "-"];
+        }
+        for (i, fd) in self.fds.iter().enumerate() {
+            if !(*fd == "-" || i > 0 && *fd == ".") {
+                return Err(OOError::CLIError { message: format!("option --null-io cannot be used with a redirection: {}", fd) });
+            }
+        }
+        self.fds = vec!["-", ".", "."];
+        self.stdin_null = true;
+        Ok(())
+    }
+
+    pub fn pipeline_spec(&self, commands: &[Vec<String>]) -> PipelineSpec {
+        PipelineSpec {
+            commands: commands.to_vec(),
+            fds: self.fds.iter().map(|s| s.to_string()).collect(),
+            envs: self.envs.iter().map(|(k, v)| (k.to_string(), v.as_ref().map(|v| v.to_string()))).collect(),
+            clear_env: self.clear_env,
+            env_passthrough: self.env_passthrough.iter().map(|s| s.to_string()).collect(),
+            working_directory: self.working_directory.map(|s| s.to_string()),
+            chdir_to_input: self.chdir_to_input,
+            force_overwrite: self.force_overwrite,
+            tempdir_placeholder: self.tempdir_placeholder.map(|s| s.to_string()),
+            interleave: self.interleave,
+            stream_labels: self.stream_labels.map(|(o, e)| (o.to_string(), e.to_string())),
+            timestamp_format: self.prefix_timestamps.map(|s| s.to_string()),
+            stdin_glob: self.glob,
+            pty: self.pty,
+            force_color: self.force_color,
+            strip_ansi_stdout: self.strip_ansi,
+            strip_ansi_stderr: self.strip_ansi_stderr,
+            line_ending: self.line_ending,
+            convert: self.convert,
+            strip_bom: self.strip_bom,
+            dereference_stdin: self.dereference_stdin,
+            stderr_on_error: self.stderr_on_error,
+            stdout_on_error: self.stdout_on_error,
+            no_sync: self.no_sync,
+            ensure_trailing_newline: self.ensure_trailing_newline,
+            atomic_stdout: self.atomic_stdout,
+            report_lines: self.report_lines,
+            abort_on_stderr: self.abort_on_stderr,
+            fail_on_empty_output: self.fail_on_empty_output,
+            touch_output: self.touch_output,
+            progress: self.progress,
+            chmod: self.chmod,
+            publish_as: self.publish_as.map(|s| s.to_string()),
+            timeout_signal: self.timeout_signal,
+            idle_timeout: self.idle_timeout,
+            stdout_hash: self.stdout_hash,
+            stdin_json: self.stdin_json.map(|s| s.to_string()),
+            stdin_tee: self.stdin_tee.map(|s| s.to_string()),
+            tee: self.tee,
+            temp_prefix: self.temp_prefix.map(|s| s.to_string()),
+            temp_suffix: self.temp_suffix.map(|s| s.to_string()),
+            skip_if_fresh: self.skip_if_fresh,
+            stdin_null: self.stdin_null || self.no_inherit_stdin,
+            chroot: self.chroot.map(|s| s.to_string()),
+            argv0: self.argv0.map(|s| s.to_string()),
+            inherited_fds: self.inherited_fds,
+            retries: self.retries,
+            retry_delay: self.retry_delay.unwrap_or_default(),
+            retry_jitter: self.retry_jitter.unwrap_or_default(),
+            append_header: self.append_header.map(|s| s.to_string()),
+            ..Default::default()
+        }
+    }
+}
+
+pub const OUTPUT_TEMPLATE_INDEX: &str = "%n";
+pub const OUTPUT_TEMPLATE_PID: &str = "%p";
+
+/// Makes the <stdout> file of the `index`-th (1-based) command line from the template of option --output-template, by
+/// replacing `%n` with the index and `%p` with the process ID of o-o.
+pub fn expand_output_template(template: &str, index: usize) -> String {
+    template.replace(OUTPUT_TEMPLATE_INDEX, &index.to_string()).replace(OUTPUT_TEMPLATE_PID, &std::process::id().to_string())
+}
+
+/// The pipe and the separator when not given by options --pipe and --separator.
+pub const DEFAULT_PIPE: &str = "I";
+pub const DEFAULT_SEPARATOR: &str = "J";
+
+impl<'s> Args<'s> {
+    /// The tokens of the pipe and separators, with the defaults for the ones not given.
+    pub fn separator_tokens(&self) -> SeparatorTokens<'s> {
+        SeparatorTokens {
+            pipe: self.pipe_str.unwrap_or(DEFAULT_PIPE),
+            separator: self.separator_str.unwrap_or(DEFAULT_SEPARATOR),
+            and_separator: self.and_separator_str,
+            or_separator: self.or_separator_str,
+        }
+    }
+
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            allow_empty_segments: self.allow_empty_segments,
+            comment: self.comment.map(|s| s.to_string()),
+            token_regex: self.token_regex,
+        }
+    }
+}
+
+/// Checks a token splitting the command line (a pipe or separator). Each argument is compared with it as a whole,
+/// so a token with whitespace (or empty) would never match one and silently leave the command line unsplit.
+pub fn validate_split_token(option: &str, token: &str, token_regex: bool) -> anyhow::Result<()> {
+    if token.is_empty() {
+        return Err(OOError::CLIError { message: format!("option {}'s argument should not be empty", option) }.into());
+    }
+    if !token_regex && token.contains(char::is_whitespace) {
+        return Err(OOError::CLIError { message: format!("option {}'s argument should not contain whitespace: {:?}", option, token) }.into());
+    }
+    Ok(())
+}
+
+/// Checks the tokens splitting the command line, each by `validate_split_token`. Two of them being the same is an
+/// error, too, as an argument equal to them could be taken as either.
+pub fn validate_separator_tokens(tokens: &SeparatorTokens, token_regex: bool) -> anyhow::Result<()> {
+    let mut named = vec![("--pipe", tokens.pipe), ("--separator", tokens.separator)];
+    named.extend(tokens.and_separator.map(|t| ("--and-separator", t)));
+    named.extend(tokens.or_separator.map(|t| ("--or-separator", t)));
+    for (i, &(option, token)) in named.iter().enumerate() {
+        validate_split_token(option, token, token_regex)?;
+        if let Some((other, _)) = named[..i].iter().find(|(_, t)| *t == token) {
+            return Err(OOError::CLIError { message: format!("options {} and {} have the same argument: {}", other, option, token) }.into());
+        }
+    }
+    Ok(())
+}
+
+/// The tokens separating the commands and the command lines.
+pub struct SeparatorTokens<'s> {
+    pub pipe: &'s str,
+    pub separator: &'s str,
+    pub and_separator: Option<&'s str>,
+    pub or_separator: Option<&'s str>,
+}
+
+/// When a command line is run, by the exit status of the last command line run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunCondition {
+    Always,
+    OnSuccess,
+    OnFailure,
+}
+
+/// The command lines, and the condition of each of them.
+pub type ConditionalPipelines = (Vec<Vec<Vec<String>>>, Vec<RunCondition>);
+
+/// Parses the command lines as `parse_pipelines_with_options`, splitting them also at the and-separator and the
+/// or-separator (options --and-separator and --or-separator), as `&&` and `||` in shell. The command line right after
+/// one of them runs only when the last command line run succeeded, or failed, respectively. As in shell, the two have
+/// the same precedence, higher than that of the separator, e.g., `a || b && c J d` runs `c` when `a` or `b` succeeds,
+/// and `d` in any case. Returns the command lines and their conditions.
+pub fn parse_conditional_pipelines(words: &[&str], tokens: &SeparatorTokens, options: &ParseOptions) -> anyhow::Result<ConditionalPipelines> {
+    let (mut pipelines, mut conditions) = (vec![], vec![]);
+    let mut condition = RunCondition::Always;
+    let mut rest = words;
+    loop {
+        let boundary = rest.iter().position(|w| Some(*w) == tokens.and_separator || Some(*w) == tokens.or_separator);
+        let part = &rest[..boundary.unwrap_or(rest.len())];
+        if part.is_empty() && condition != RunCondition::Always {
+            return Err(OOError::CLIError { message: "empty command line (unexpected and-separator or or-separator)".to_string() }.into());
+        }
+        let parsed = parse_pipelines_with_options(part, tokens.pipe, tokens.separator, options)?;
+        conditions.push(condition);
+        conditions.resize(conditions.len() + parsed.len() - 1, RunCondition::Always);
+        pipelines.extend(parsed);
+
+        let Some(i) = boundary else {
+            break;
+        };
+        condition = if Some(rest[i]) == tokens.and_separator { RunCondition::OnSuccess } else { RunCondition::OnFailure };
+        rest = &rest[i + 1..];
+    }
+    Ok((pipelines, conditions))
+}
+
+/// Validates that a stream attached to an inherited descriptor (options --stdin-fd, --stdout-fd and --stderr-fd) is not redirected otherwise.
+fn do_validate_inherited_fds(fds: &[&str], inherited_fds: &[Option<i32>; 3]) -> Result<(), OOError> {
+    for (i, name) in ["stdin", "stdout", "stderr"].iter().enumerate() {
+        if inherited_fds[i].is_some() && fds[i] != "-" {
+            return Err(OOError::CLIError { message: format!("option --{}-fd requires `-` as {}: {}", name, name, fds[i]) });
+        }
+    }
+    Ok(())
+}
+
+/// Checks that option --atomic-stdout is given with a named stdout file, which is not appended to.
+fn do_validate_atomic_stdout(fds: &[&str], atomic_stdout: bool) -> Result<(), OOError> {
+    if !atomic_stdout {
+        return Ok(());
+    }
+    let err = |message: String| Err(OOError::CLIError { message });
+    match fds[1] {
+        "-" | "=" | "." => err(format!("option --atomic-stdout requires a file name for <stdout>: {}", fds[1])),
+        fd if split_append_flag(fd).1 => err(format!("option --atomic-stdout cannot be used with the append prefix `+`: {}", fd)),
+        _ => Ok(()),
+    }
+}
+
+/// Checks that option --publish-as is given with a named stdout file, which is not appended to nor read as `=`.
+fn do_validate_publish_as(fds: &[&str], publish_as: Option<&str>) -> Result<(), OOError> {
+    let Some(publish_as) = publish_as else {
+        return Ok(());
+    };
+    let err = |message: String| Err(OOError::CLIError { message });
+    if fds[0] == "=" {
+        return err("option --publish-as cannot be used with `=` as <stdin>".to_string());
+    }
+    match fds[1] {
+        "-" | "=" | "." => err(format!("option --publish-as requires a file name for <stdout>: {}", fds[1])),
+        fd if split_append_flag(fd).1 => err(format!("option --publish-as cannot be used with the append prefix `+`: {}", fd)),
+        fd if fd == publish_as => err(format!("option --publish-as's argument is the same file as <stdout>: {}", fd)),
+        _ => Ok(()),
+    }
+}
+
+/// Validates the options and the fds, as o-o does before running the command lines. Only the notation is checked (see
+/// `do_validate_fd_names`), so o-o itself checks the files and the commands, after this, which `validate` leaves out.
+pub fn validate_args(a: &Args) -> anyhow::Result<()> {
+    let err = |message: &str| Err(OOError::CLIError { message: message.to_string() }.into());
+
+    do_validate_fd_names(&a.fds, a.force_overwrite && !a.atomic_stdout)?;
+    do_validate_atomic_stdout(&a.fds, a.atomic_stdout)?;
+    do_validate_publish_as(&a.fds, a.publish_as)?;
+    do_validate_glob(&a.fds, a.glob)?;
+    do_validate_stdout_on_error(&a.fds, a.stdout_on_error.is_some())?;
+    do_validate_inherited_fds(&a.fds, &a.inherited_fds)?;
+    if a.stdin_json.is_some() && (a.fds[0] != "-" || a.stdin_null) {
+        return err("option --stdin-json cannot be used with a stdin file or --stdin-null");
+    }
+    if a.stdin_null && a.fds[0] != "-" {
+        return Err(OOError::CLIError { message: format!("option --stdin-null cannot be used with a stdin file: {}", a.fds[0]) }.into());
+    }
+    if a.timeout_signal.is_some() && a.deadline.is_none() {
+        return err("option --signal-on-timeout requires --deadline");
+    }
+    if a.max_parallel.is_some() && !a.keep_going {
+        return err("option --max-parallel requires --keep-going");
+    }
+    if a.lock_timeout.is_some() && a.lock_file.is_none() {
+        return err("option --lock-timeout requires --lock-file");
+    }
+    Ok(())
+}
+
+/// Validates the options of an o-o command given as the second or later command line, which has to have a command
+/// and none of the options applying to the whole command line. Only the notation is checked, as in `validate_args`.
+pub fn validate_sub_command(sub_a: &Args, glob: bool) -> anyhow::Result<()> {
+    let err = |message: &str| Err(OOError::CLIError { message: message.to_string() }.into());
+
+    if sub_a.command_line.is_empty() {
+        return err("nested o-o has no command");
+    }
+    if sub_a.debug_info {
+        return err("invalid option used in sub-command: --debug-info");
+    }
+    if sub_a.pipe_str.is_some() {
+        return err("invalid option used in sub-command: --pipe");
+    }
+    if sub_a.separator_str.is_some() {
+        return err("invalid option used in sub-command: --separator");
+    }
+    if sub_a.token_regex {
+        return err("invalid option used in sub-command: --token-regex");
+    }
+    if sub_a.and_separator_str.is_some() || sub_a.or_separator_str.is_some() {
+        return err("invalid option used in sub-command: --and-separator or --or-separator");
+    }
+    if sub_a.args0_file.is_some() {
+        return err("invalid option used in sub-command: --args0-file");
+    }
+    if sub_a.tempdir_placeholder.is_some() {
+        return err("invalid option used in sub-command: --tempdir-placeholder=");
+    }
+
+    do_validate_fd_names(&sub_a.fds, sub_a.force_overwrite)?;
+    do_validate_glob(&sub_a.fds, sub_a.glob || glob)?;
+    do_validate_stdout_on_error(&sub_a.fds, sub_a.stdout_on_error.is_some())?;
+    Ok(())
+}
+
+/// Validates the command lines split from the arguments, as o-o does before running them: the options needing
+/// a single command line or ones without conditions, the o-o commands among them (by `validate_sub_command`), and
+/// the files of the command lines run in parallel (by `do_validate_parallel`).
+pub fn validate_command_lines(a: &Args, pipelines: &[Vec<Vec<String>>], conditions: &[RunCondition]) -> anyhow::Result<()> {
+    let err = |message: &str| Err(OOError::CLIError { message: message.to_string() }.into());
+    // As o-o runs it, `- =` is `- -`
+    let stdout_of = |fds: &[&str]| if fds[0] == "-" && fds[1] == "=" { "-".to_string() } else { fds[1].to_string() };
+
+    if a.capture.is_some() {
+        if pipelines.len() != 1 || pipelines[0].len() != 1 {
+            return err("option --capture cannot be used with a pipe or multiple command lines");
+        }
+        if stdout_of(&a.fds) != "-" || a.fds[2] != "-" {
+            return err("option --capture cannot be used with a redirection of <stdout> or <stderr>");
+        }
+    }
+    let parallel = a.max_parallel.is_some_and(|n| n > 1);
+    if parallel && conditions.iter().any(|&c| c != RunCondition::Always) {
+        return err("option --max-parallel cannot be used with the and-separator or or-separator");
+    }
+
+    // The fds of each command line as it is run. The second and later ones do not redirect, unless given as an o-o command
+    let template_stdout = |index: usize, stdout: String| match a.output_template {
+        Some(template) if stdout == "-" => expand_output_template(template, index),
+        _ => stdout,
+    };
+    let mut fds = vec![[a.fds[0].to_string(), template_stdout(1, stdout_of(&a.fds)), a.fds[2].to_string()]];
+    for pl in pipelines.iter().skip(1) {
+        let pl0: Vec<&str> = pl.first().map_or(vec![], |c| c.iter().map(AsRef::as_ref).collect());
+        if pl0.len() >= 2 && pl0[0].starts_with(STAGE_DIRECTORY_PREFIX) && pl0[1] == "o-o" {
+            return Err(OOError::CLIError { message: format!("{}DIR cannot precede sub-command o-o; use its --working-directory option", STAGE_DIRECTORY_PREFIX) }.into());
+        }
+        if pl0.first() == Some(&"o-o") {
+            let sub_a = Args::parse_unchecked(&pl0)?;
+            if sub_a.help || sub_a.version {
+                continue; // o-o prints the usage or version, instead of running the command lines
+            }
+            validate_sub_command(&sub_a, a.glob)?;
+            fds.push([sub_a.fds[0].to_string(), stdout_of(&sub_a.fds), sub_a.fds[2].to_string()]);
+        } else {
+            fds.push(["-".to_string(), template_stdout(fds.len() + 1, "-".to_string()), "-".to_string()]);
+        }
+    }
+    if parallel && fds.len() > 1 {
+        do_validate_parallel(&fds)?;
+    }
+    Ok(())
+}
@@ -1,10 +1,54 @@
-use std::fs::{File, OpenOptions};
-use std::path::PathBuf;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::cell::OnceCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
+use thiserror::Error;
 
 use duct::cmd;
-use tempfile::{NamedTempFile, Builder};
+use tempfile::Builder;
+
+pub mod args;
+
+#[derive(Error, Debug)]
+pub enum OOError {
+    #[error("o-o: {}", .message)]
+    CLIError { message: String },
+    #[error("o-o: invalid working directory: {} ({})", .path, .reason)]
+    WorkingDirectory { path: String, reason: String },
+    #[error("o-o: deadline exceeded")]
+    DeadlineExceeded,
+    #[error("o-o: killed the command, which wrote no output for {:?}", .timeout)]
+    IdleTimeout { timeout: Duration },
+    #[error("o-o: failed to replace {}: {}", .path, .reason)]
+    ReplaceFile { path: String, reason: String },
+    #[error("o-o: spawn limit reached: {} subprocesses spawned, limit is {}", .spawned, .limit)]
+    SpawnLimit { spawned: usize, limit: usize },
+    #[error("o-o: cannot read stdin file: {} ({})", .path, .reason)]
+    StdinFile { path: String, reason: String },
+}
+
+/// Exit code of o-o when the deadline (option --deadline) passes, the same as that of timeout(1).
+pub const EXIT_CODE_DEADLINE_EXCEEDED: i32 = 124;
+
+/// Exit code of o-o when the command lines succeeded but the stdin file could not be replaced with the output of `=`.
+pub const EXIT_CODE_REPLACE_FAILED: i32 = 3;
+
+/// Exit code of o-o when running the next command line would exceed the number of subprocesses (option --spawn-limit).
+pub const EXIT_CODE_SPAWN_LIMIT: i32 = 1;
+
+/// Exit code of o-o when the command lines exited with 0 but wrote to stderr (option --abort-on-stderr).
+pub const EXIT_CODE_STDERR_WRITTEN: i32 = 1;
+
+/// Exit code of o-o when the command lines exited with 0 but wrote nothing to stdout (option --fail-on-empty-output).
+pub const EXIT_CODE_EMPTY_OUTPUT: i32 = 1;
 
 #[cfg(not(windows))]
 pub fn command_exists(cmd: &str) -> bool {
@@ -15,34 +59,2420 @@ pub fn command_exists(cmd: &str) -> bool {
     !output.trim().is_empty()
 }
 
-pub fn open_file_with_mode(path: &str) -> Result<File> {
+pub fn split_append_flag(file_name: &str) -> (&str, bool) {
+    if let Some(stripped) = file_name.strip_prefix('+') {
+        (stripped, true)
+    } else {
+        (file_name, false)
+    }
+}
+
+/// How an output file is opened, which is decided by the `+` prefix of the file name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Truncate,
+    Append,
+}
+
+/// Opens a file for writing, appending to it when the name has the `+` prefix and overwriting it otherwise.
+/// The mode used is returned along with the file, so that callers can tell "appending to X" from "overwriting X".
+pub fn open_file_with_mode(path: &str) -> Result<(File, OpenMode)> {
     let mut options = OpenOptions::new();
     options.write(true).create(true);
 
     let (mode, clean_path) = if let Some(s) = path.strip_prefix('+') {
-        (true, s)
+        (OpenMode::Append, s)
     } else {
-        (false, path)
+        (OpenMode::Truncate, path)
     };
 
-    if mode {
-        options.append(true);
-    } else {
-        options.truncate(true);
-    }
+    match mode {
+        OpenMode::Append => options.append(true),
+        OpenMode::Truncate => options.truncate(true),
+    };
 
     let file = options.open(clean_path)
         .with_context(|| format!("Failed to open file: {}", clean_path))?;
 
+    Ok((file, mode))
+}
+
+/// Opens an output file as `open_file_with_mode`, writing the header line first when appending to the file.
+/// The token `%t` in the header is replaced with the current time.
+pub fn open_output_file(path: &str, append_header: Option<&str>) -> Result<File> {
+    let (mut file, mode) = open_file_with_mode(path)?;
+    if let (Some(header), OpenMode::Append) = (append_header, mode) {
+        let header = header.replace("%t", &format_timestamp(DEFAULT_TIMESTAMP_FORMAT));
+        writeln!(file, "{}", header)?;
+    }
     Ok(file)
 }
 
-pub fn create_temp_file(tempdir_placeholder: &Option<&str>) -> Result<PathBuf> {
+pub fn open_stdin_file(path: &str) -> Result<File> {
+    OpenOptions::new().read(true).open(path).map_err(|e| stdin_file_error(path, &e).into())
+}
+
+/// The error of the stdin file which cannot be read, with the reason without the OS error code,
+/// e.g. "No such file or directory" rather than "No such file or directory (os error 2)".
+fn stdin_file_error(path: &str, e: &io::Error) -> OOError {
+    let message = e.to_string();
+    let reason = match e.raw_os_error() {
+        Some(code) => message.strip_suffix(&format!(" (os error {})", code)).unwrap_or(&message),
+        None => &message,
+    };
+    OOError::StdinFile { path: path.to_string(), reason: reason.to_string() }
+}
+
+/// Tells whether the string contains any of the metacharacters of glob patterns (`*`, `?`, `[`).
+pub fn has_glob_metacharacters(s: &str) -> bool {
+    s.contains(['*', '?', '['])
+}
+
+/// Expands a glob pattern of the stdin file into the matching files, in sorted order.
+/// No match is an error.
+pub fn expand_stdin_glob(pattern: &str) -> Result<Vec<String>> {
+    let err = |message: String| -> anyhow::Error { OOError::CLIError { message }.into() };
+
+    let entries = glob::glob(pattern).map_err(|e| err(format!("invalid glob pattern: {} ({})", pattern, e)))?;
+    let mut paths: Vec<String> = vec![];
+    for entry in entries {
+        let path = entry.map_err(|e| err(format!("cannot read stdin file: {}", e)))?;
+        paths.push(path.to_string_lossy().into_owned());
+    }
+    if paths.is_empty() {
+        return Err(err(format!("no files match the stdin pattern: {}", pattern)));
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// What is connected to the standard input of a pipeline.
+enum StdinSource {
+    File(File),
+    /// Files written to the pipe by a thread (concatenated, or with the BOM stripped).
+    Concatenated(os_pipe::PipeReader),
+}
+
+impl StdinSource {
+    fn redirect(self, pipeline: duct::Expression) -> duct::Expression {
+        match self {
+            StdinSource::File(file) => pipeline.stdin_file(file),
+            StdinSource::Concatenated(reader) => pipeline.stdin_file(reader),
+        }
+    }
+
+    fn into_reader(self) -> Box<dyn Read + Send> {
+        match self {
+            StdinSource::File(file) => Box::new(file),
+            StdinSource::Concatenated(reader) => Box::new(reader),
+        }
+    }
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Reads a JSON array from the file and starts a thread writing its elements to a pipe, one per line (option --stdin-json).
+/// The whole file is parsed here, so that malformed JSON is reported before the pipeline is spawned.
+fn open_stdin_json(path: &str) -> Result<StdinSource> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read JSON file: {}", path))?;
+    let records = json_array_to_ndjson(&content)
+        .map_err(|message| OOError::CLIError { message: format!("invalid JSON file {}: {}", path, message) })?;
+
+    let (reader, mut writer) = os_pipe::pipe()?;
+    thread::spawn(move || {
+        // A write error means the pipeline has stopped reading, so the rest is discarded
+        let _ = (|| -> io::Result<()> {
+            for record in records {
+                writer.write_all(record.as_bytes())?;
+                writer.write_all(b"\n")?;
+            }
+            Ok(())
+        })();
+    });
+    Ok(StdinSource::Concatenated(reader))
+}
+
+/// Opens the stdin file, or when there are multiple files (or the BOM is stripped), starts a thread writing them to a pipe.
+/// All files are opened here, so that a missing one is reported before the pipeline is spawned.
+/// The thread streams the files through the pipe in fixed-size chunks, so that a huge input is never held in memory.
+fn open_stdin_files(paths: &[String], strip_bom: bool) -> Result<StdinSource> {
+    let mut files = paths.iter().map(|p| open_stdin_file(p)).collect::<Result<Vec<File>>>()?;
+    if files.len() == 1 && !strip_bom {
+        return Ok(StdinSource::File(files.pop().unwrap()));
+    }
+
+    let (reader, mut writer) = os_pipe::pipe()?;
+    thread::spawn(move || {
+        // A write error means the pipeline has stopped reading, so the rest is discarded
+        let _ = (|| -> io::Result<()> {
+            for (i, mut file) in files.into_iter().enumerate() {
+                if i == 0 && strip_bom {
+                    let mut head = vec![];
+                    (&mut file).take(UTF8_BOM.len() as u64).read_to_end(&mut head)?;
+                    if head != UTF8_BOM {
+                        writer.write_all(&head)?;
+                    }
+                }
+                io::copy(&mut file, &mut writer)?;
+            }
+            Ok(())
+        })();
+    });
+    Ok(StdinSource::Concatenated(reader))
+}
+
+/// Starts a thread copying the stdin to both the tee file and a pipe, which becomes the stdin of the pipeline (option --stdin-tee).
+/// Each chunk is written to the tee file first, so that the file has all the bytes the pipeline has read.
+fn tee_stdin(mut source: Box<dyn Read + Send>, path: &str) -> Result<StdinSource> {
+    let mut tee = File::create(path).with_context(|| format!("Failed to create stdin tee file: {}", path))?;
+    let (reader, mut writer) = os_pipe::pipe()?;
+    thread::spawn(move || {
+        // A write error means the pipeline has stopped reading, so the rest is discarded
+        let _ = (|| -> io::Result<()> {
+            let mut buf = [0u8; 8192];
+            loop {
+                let n = match source.read(&mut buf) {
+                    Ok(0) => return Ok(()),
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                };
+                tee.write_all(&buf[..n])?;
+                writer.write_all(&buf[..n])?;
+            }
+        })();
+    });
+    Ok(StdinSource::Concatenated(reader))
+}
+
+fn open_stdin(spec: &PipelineSpec) -> Result<Option<StdinSource>> {
+    let source = open_stdin_source(spec)?;
+    let Some(path) = &spec.stdin_tee else {
+        return Ok(source);
+    };
+    let reader: Box<dyn Read + Send> = match (source, spec.inherited_fds[0]) {
+        (Some(source), _) => source.into_reader(),
+        (None, Some(fd)) => Box::new(dup_inherited_fd(fd)?),
+        (None, None) if spec.stdin_null => Box::new(io::empty()),
+        (None, None) => Box::new(io::stdin()),
+    };
+    tee_stdin(reader, path).map(Some)
+}
+
+fn open_stdin_source(spec: &PipelineSpec) -> Result<Option<StdinSource>> {
+    if let Some(path) = &spec.stdin_json {
+        return open_stdin_json(path).map(Some);
+    }
+    let stdin = &spec.fds[0];
+    if stdin == "-" {
+        return Ok(None);
+    }
+    let paths = if spec.stdin_glob && has_glob_metacharacters(stdin) {
+        expand_stdin_glob(stdin)?
+    } else {
+        vec![stdin.clone()]
+    };
+    open_stdin_files(&paths, spec.strip_bom).map(Some)
+}
+
+/// Makes a name of a temporary file (in the directory if given), which starts with the prefix and ends with the suffix.
+/// Without a prefix, it is `tempfile` in the directory, and that of `tempfile::NamedTempFile` otherwise.
+pub fn create_temp_file(tempdir_placeholder: &Option<&str>, prefix: Option<&str>, suffix: Option<&str>) -> Result<PathBuf> {
+    let mut builder = Builder::new();
+    match (prefix, tempdir_placeholder) {
+        (Some(prefix), _) => builder.prefix(prefix),
+        (None, Some(_)) => builder.prefix("tempfile"),
+        (None, None) => &mut builder,
+    };
+    if let Some(suffix) = suffix {
+        builder.suffix(suffix);
+    }
     let temp_file = if let Some(dir) = tempdir_placeholder {
-        Builder::new().prefix("tempfile").tempfile_in(dir)?
+        builder.tempfile_in(dir)?
     } else {
-        NamedTempFile::new()?
+        builder.tempfile()?
     };
 
     Ok(temp_file.path().to_path_buf())
 }
+
+/// The temporary file of `=`, held open through the run, so that o-o can sync it and check its size before the swap.
+struct TempOutput {
+    path: PathBuf,
+    file: File,
+    /// The file to be replaced, when it is not the stdin file (option --atomic-stdout).
+    target: Option<String>,
+    /// The count of the bytes o-o has written to the file, when the output is copied by o-o (see `counting_writer`).
+    copied: OnceCell<Arc<AtomicU64>>,
+}
+
+impl TempOutput {
+    fn create(tempdir_placeholder: &Option<&str>, spec: &PipelineSpec) -> Result<TempOutput> {
+        let path = create_temp_file(tempdir_placeholder, spec.temp_prefix.as_deref(), spec.temp_suffix.as_deref())?;
+        let file = File::create(&path).with_context(|| format!("Failed to create temporary file: {}", path.display()))?;
+        Ok(TempOutput { path, file, target: None, copied: OnceCell::new() })
+    }
+
+    /// Creates the temporary file for a named stdout file, in the directory of it so that the rename stays in a file system.
+    fn create_for(target: &str, spec: &PipelineSpec) -> Result<TempOutput> {
+        let dir = match Path::new(target).parent() {
+            Some(p) if !p.as_os_str().is_empty() => p,
+            _ => Path::new("."),
+        };
+        let temp = TempOutput::create(&dir.to_str(), spec)?;
+        Ok(TempOutput { target: Some(target.to_string()), ..temp })
+    }
+
+    /// Another handle to the file, for a writer of the output.
+    fn writer(&self) -> Result<File> {
+        Ok(self.file.try_clone()?)
+    }
+
+    /// A writer of the output copied by o-o to the file, counting the bytes written, which are checked against the
+    /// length of the file before the swap. It has to be the only writer, as the output of a subprocess is not counted.
+    fn counting_writer(&self) -> Result<CountingWriter> {
+        let count = self.copied.get_or_init(|| Arc::new(AtomicU64::new(0))).clone();
+        Ok(CountingWriter { file: self.writer()?, count })
+    }
+}
+
+/// A writer counting the bytes written to the file, for `TempOutput::counting_writer`.
+struct CountingWriter {
+    file: File,
+    count: Arc<AtomicU64>,
+}
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.count.fetch_add(n as u64, Ordering::SeqCst);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The interval of the polling of a running pipeline, for its deadline and the exits of its stages.
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Resolves environment-variable assignments into an ordered list without duplicate keys.
+/// Assignments are given in increasing order of precedence (the parent's `-e`/`-u` options first,
+/// then a sub-command's `-e`/`-u` options), so a later assignment of a key overrides an earlier one
+/// while the key keeps the position of its first occurrence.
+/// A value of `None` means the variable is removed (`-u VAR`), which is different from
+/// setting it to the empty string (`-e VAR=`).
+pub fn resolve_envs<'s>(envs: &[(&'s str, Option<&'s str>)]) -> Vec<(&'s str, Option<&'s str>)> {
+    let mut resolved: Vec<(&str, Option<&str>)> = vec![];
+    for &(key, value) in envs {
+        if let Some(entry) = resolved.iter_mut().find(|(k, _)| *k == key) {
+            entry.1 = value;
+        } else {
+            resolved.push((key, value));
+        }
+    }
+
+    resolved
+}
+
+/// Reads the environment variables from a file (option --env-file). A file whose name ends with `.json` is read as
+/// a JSON object of strings, and any other as lines of `KEY=VALUE` (see `parse_dotenv`).
+pub fn load_env_file(path: &str) -> Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read env file: {}", path))?;
+    let parsed = if path.ends_with(".json") { parse_json_env(&content) } else { parse_dotenv(&content) };
+    parsed.map_err(|message| OOError::CLIError { message: format!("invalid env file {}: {}", path, message) }.into())
+}
+
+/// Parses lines of `KEY=VALUE`. Empty lines and lines starting with `#` are skipped, and a leading `export ` is ignored.
+/// A value in double quotes may contain the escapes `\n`, `\t`, `\"` and `\\`; one in single quotes is taken literally.
+/// An error is a message with the line and column of the problem.
+pub fn parse_dotenv(content: &str) -> std::result::Result<Vec<(String, String)>, String> {
+    let mut envs = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let err = |column: usize, reason: &str| Err(format!("line {}, column {}: {}", i + 1, column + 1, reason));
+
+        let body = line.trim_start();
+        if body.is_empty() || body.starts_with('#') {
+            continue;
+        }
+        let body = body.strip_prefix("export ").map_or(body, str::trim_start);
+        let offset = line.len() - body.len();
+        let Some(eq) = body.find('=') else {
+            return err(offset, "expected `KEY=VALUE`");
+        };
+        let key = body[..eq].trim_end();
+        if !is_env_name(key) {
+            return err(offset, &format!("invalid variable name: {}", key));
+        }
+
+        let raw = body[eq + 1..].trim_start();
+        let value_column = offset + body.len() - raw.len();
+        let (value, rest) = match raw.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let mut value = String::new();
+                let mut chars = raw.char_indices().skip(1);
+                let mut end = None;
+                while let Some((j, c)) = chars.next() {
+                    match c {
+                        _ if c == quote => {
+                            end = Some(j + 1);
+                            break;
+                        }
+                        '\\' if quote == '"' => match chars.next() {
+                            Some((_, 'n')) => value.push('\n'),
+                            Some((_, 't')) => value.push('\t'),
+                            Some((_, e @ ('"' | '\\'))) => value.push(e),
+                            Some((k, _)) => return err(value_column + k - 1, "unknown escape sequence"),
+                            None => break,
+                        },
+                        _ => value.push(c),
+                    }
+                }
+                let Some(end) = end else {
+                    return err(value_column, "unterminated quoted value");
+                };
+                (value, &raw[end..])
+            }
+            _ => (raw.trim_end().to_string(), ""),
+        };
+        let rest_trimmed = rest.trim_start();
+        if !(rest_trimmed.is_empty() || rest_trimmed.starts_with('#')) {
+            return err(line.len() - rest_trimmed.len(), "unexpected characters after the quoted value");
+        }
+        envs.push((key.to_string(), value));
+    }
+    Ok(envs)
+}
+
+/// Tells whether the string is a name of an environment variable, i.e., an identifier of shell.
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Converts a JSON array into its elements, each in the compact form (without the whitespace outside strings),
+/// i.e., the records of NDJSON. An error is a message with the line and column of the problem.
+pub fn json_array_to_ndjson(content: &str) -> std::result::Result<Vec<String>, String> {
+    let elements: Vec<serde_json::Value> = serde_json::from_str(content).map_err(|e| json_error_message(&e))?;
+    Ok(elements.iter().map(serde_json::Value::to_string).collect())
+}
+
+/// Parses a JSON object whose values are strings, e.g., `{"KEY": "VALUE"}`, into the variables in the order of their names.
+/// An error is a message with the line and column of the problem.
+pub fn parse_json_env(content: &str) -> std::result::Result<Vec<(String, String)>, String> {
+    let envs: BTreeMap<String, String> = serde_json::from_str(content).map_err(|e| json_error_message(&e))?;
+    Ok(envs.into_iter().collect())
+}
+
+/// Formats an error of serde_json as `line L, column C: REASON`, as the errors of `parse_dotenv`.
+fn json_error_message(e: &serde_json::Error) -> String {
+    let message = e.to_string();
+    let position = format!(" at line {} column {}", e.line(), e.column());
+    format!("line {}, column {}: {}", e.line(), e.column(), message.strip_suffix(&position).unwrap_or(&message))
+}
+
+pub fn do_validate_fds(fds: &[&str], force_overwrite: bool) -> std::result::Result<(), OOError> {
+    if fds.len() >= 3 {
+        for fd in &fds[1..] {
+            if command_exists(fd) {
+                return Err(OOError::CLIError { message: format!("out/err looks a command: {}\n> (Use `--` to explicitly separate command from out/err)", fd)})
+            }
+        }
+    }
+
+    do_validate_fd_names(fds, force_overwrite)
+}
+
+/// Validates the fds as `do_validate_fds` does, except for looking up the commands, i.e., only by their notation.
+pub fn do_validate_fd_names(fds: &[&str], force_overwrite: bool) -> std::result::Result<(), OOError> {
+    let err = |message: &str| {
+        Err(OOError::CLIError { message: message.to_string() })
+    };
+
+    if fds.len() < 3 {
+        return err("requires three arguments: stdin, stdout and stderr");
+    }
+
+    if split_append_flag(fds[0]).1 {
+        return Err(OOError::CLIError { message: format!("can not specify the append prefix `+` for stdin, which is only read: {}", fds[0]) });
+    }
+
+    for i in 0..fds.len() {
+        if fds[i] == "+-" || fds[i] == "+=" {
+            return err("not possible to use `-` or `=` in combination with `+`");
+        }
+        if !(fds[i] == "-" || fds[i] == "=" || fds[i] == ".") {
+            for j in i + 1..fds.len() {
+                if split_append_flag(fds[j]).0 == split_append_flag(fds[i]).0 {
+                    let hint = match (i, j) {
+                        (0, 1) => "Use `=` for <stdout> to overwrite the stdin file with the output",
+                        (1, 2) => "Use `=` for <stderr> to merge it into <stdout>, e.g. `- out.txt =`",
+                        _ => "Explicitly use `=` when dealing with the same file",
+                    };
+                    let names = ["<stdin>", "<stdout>", "<stderr>"];
+                    return Err(OOError::CLIError {
+                        message: format!("{} and {} are the same file: {}\n> ({})", names[i], names[j], split_append_flag(fds[i]).0, hint),
+                    });
+                }
+            }
+        }
+    }
+
+    if force_overwrite {
+        if fds[0] == "-" {
+            return err("option --force-overwrite requires a real file name");
+        }
+        if fds[1] != "=" && fds[0] != "=" {
+            return err("option --force-overwrite is only valid when <stdout> is `=`");
+        }
+    }
+
+    if fds[0] == "." {
+        return err("can not specify `.` as stdin");
+    }
+    if fds[0] == "=" && (!is_named_file(fds[1]) || split_append_flag(fds[1]).1) {
+        return Err(OOError::CLIError { message: format!("`=` as stdin requires a file name for <stdout>, without `+`: {}", fds[1]) });
+    }
+
+    Ok(())
+}
+
+/// Validates an o-o command line without running it, e.g., for an editor or linter. `argv` is the arguments including
+/// the program name, as `std::env::args()`. The arguments are parsed and checked by the same functions as o-o does
+/// before running the command lines (`args::validate_args` and `args::validate_command_lines`), and the first error is
+/// returned. No file is accessed and nothing is spawned, so the checks needing them (e.g., of the working directory, or
+/// whether a file name of stdout looks a command) are left out, as are the command lines of --commands-file and --args0-file.
+pub fn validate(argv: &[&str]) -> std::result::Result<(), OOError> {
+    let to_oo_error = |e: anyhow::Error| match e.downcast::<OOError>() {
+        Ok(e) => e,
+        Err(e) => OOError::CLIError { message: e.to_string() },
+    };
+
+    let a = args::Args::parse(argv).map_err(to_oo_error)?;
+    if a.help || a.version || a.dump_config {
+        return Ok(());
+    }
+    let tokens = a.separator_tokens();
+    args::validate_separator_tokens(&tokens, a.token_regex).map_err(to_oo_error)?;
+    let command_lines = if a.convert.is_some() {
+        Some((vec![vec![]], vec![args::RunCondition::Always])) // o-o itself works as the command
+    } else if a.commands_file.is_none() && a.args0_file.is_none() {
+        Some(args::parse_conditional_pipelines(&a.command_line, &tokens, &a.parse_options()).map_err(to_oo_error)?)
+    } else {
+        None
+    };
+    args::validate_args(&a).map_err(to_oo_error)?;
+    if let Some((pipelines, conditions)) = command_lines {
+        args::validate_command_lines(&a, &pipelines, &conditions).map_err(to_oo_error)?;
+    }
+    Ok(())
+}
+
+/// Validates the redirections when the stdin file is expanded as a glob pattern (option --glob).
+pub fn do_validate_glob(fds: &[&str], glob: bool) -> std::result::Result<(), OOError> {
+    if glob && (fds[1] == "=" && has_glob_metacharacters(fds[0]) || fds[0] == "=" && has_glob_metacharacters(fds[1])) {
+        return Err(OOError::CLIError { message: "option --glob can not be used with `=` as stdout when <stdin> is a pattern".to_string() });
+    }
+
+    Ok(())
+}
+
+/// Validates the redirections when stdout is written out only on failure (option --stdout-on-error).
+pub fn do_validate_stdout_on_error(fds: &[&str], stdout_on_error: bool) -> std::result::Result<(), OOError> {
+    if stdout_on_error && fds[1] == "=" {
+        return Err(OOError::CLIError { message: "option --stdout-on-error can not be used with `=` as stdout".to_string() });
+    }
+
+    Ok(())
+}
+
+/// Validates the working directory (option --working-directory), which has to be an existing, readable directory.
+pub fn validate_working_directory(path: &str) -> std::result::Result<(), OOError> {
+    let err = |reason: &str| Err(OOError::WorkingDirectory { path: path.to_string(), reason: reason.to_string() });
+
+    match fs::metadata(path) {
+        Err(_) => err("no such directory"),
+        Ok(m) if !m.is_dir() => err("not a directory"),
+        Ok(_) => match fs::read_dir(path) {
+            Err(_) => err("not readable"),
+            Ok(_) => Ok(()),
+        },
+    }
+}
+
+/// Validates that pipelines run in parallel (option --max-parallel) are independent, i.e., no file
+/// written by one of them is written or read by another.
+/// The stdin file of `=` counts as written, since it is overwritten.
+pub fn do_validate_parallel(fds_list: &[[String; 3]]) -> std::result::Result<(), OOError> {
+    let is_file = |fd: &str| !(fd == "-" || fd == "=" || fd == ".");
+    let files = |fds: &[String; 3]| -> (Vec<String>, Vec<String>) {
+        let mut reads = vec![];
+        let mut writes = vec![];
+        if is_file(&fds[0]) {
+            if fds[1] == "=" { &mut writes } else { &mut reads }.push(fds[0].clone());
+        }
+        for fd in &fds[1..] {
+            if is_file(fd) {
+                writes.push(split_append_flag(fd).0.to_string());
+            }
+        }
+        (reads, writes)
+    };
+
+    let files: Vec<(Vec<String>, Vec<String>)> = fds_list.iter().map(files).collect();
+    for (i, (_, writes)) in files.iter().enumerate() {
+        for (j, (other_reads, other_writes)) in files.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            if let Some(f) = writes.iter().find(|f| other_reads.contains(f) || other_writes.contains(f)) {
+                return Err(OOError::CLIError { message: format!("command lines run in parallel share a file: {}", f) });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A pipeline to run, i.e., command lines connected with pipes, and how its standard I/O are redirected.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineSpec {
+    pub commands: Vec<Vec<String>>,
+    /// stdin, stdout and stderr, in the same notation as the command-line arguments of o-o (`-`, `=`, `.`, `+file`).
+    /// A stdin of `=` reads the stdout file, which is then replaced as by a stdout of `=`.
+    pub fds: Vec<String>,
+    pub envs: Vec<(String, Option<String>)>,
+    pub working_directory: Option<String>,
+    /// Runs the commands in the directory of the stdin file, when `working_directory` is not given.
+    pub chdir_to_input: bool,
+    pub force_overwrite: bool,
+    pub tempdir_placeholder: Option<String>,
+    /// Reads stdout and stderr by o-o itself and writes them out in the order of arrival.
+    pub interleave: bool,
+    /// Labels of stdout and stderr. Each line is prefixed with `LABEL: `.
+    pub stream_labels: Option<(String, String)>,
+    /// strftime-like format of the timestamps prefixed to the lines written to files.
+    pub timestamp_format: Option<String>,
+    /// Expands the stdin file as a glob pattern. Multiple matching files are concatenated in sorted order.
+    pub stdin_glob: bool,
+    /// Connects the stdout of the pipeline to a pseudo-terminal, whose output is written to the stdout's destination.
+    pub pty: bool,
+    /// Sets the environment variables of `FORCE_COLOR_ENVS`.
+    pub force_color: bool,
+    /// Removes ANSI escape sequences from stdout.
+    pub strip_ansi_stdout: bool,
+    /// Removes ANSI escape sequences from stderr.
+    pub strip_ansi_stderr: bool,
+    /// Converts the line endings of the output written to files.
+    pub line_ending: Option<LineEnding>,
+    /// Skips a UTF-8 BOM at the beginning of the stdin file.
+    pub strip_bom: bool,
+    /// Resolves the stdin file into its canonical path before opening it, so that a symbolic link is followed both in
+    /// reading and in the write-back of `=`. Otherwise, `=` replaces the link itself with a regular file.
+    pub dereference_stdin: bool,
+    /// Holds stderr in memory (up to the given number of bytes, keeping the last ones) and writes it out only when the pipeline fails.
+    pub stderr_on_error: Option<usize>,
+    /// Holds stdout in memory, in the same way as `stderr_on_error`.
+    pub stdout_on_error: Option<usize>,
+    /// Skips flushing the written files to the storage device after the run, trading durability for speed.
+    pub no_sync: bool,
+    /// Appends a newline to the output written to a file (including `=`), when it is not empty and does not end with one.
+    pub ensure_trailing_newline: bool,
+    /// Writes a named stdout file via a temporary file renamed onto it when the pipeline succeeds (or `force_overwrite`), as `=` does.
+    pub atomic_stdout: bool,
+    /// Reports the number of the lines written to the stdout file (or `=`) to stderr, after the run.
+    pub report_lines: bool,
+    /// Kills the pipeline once it writes anything to stderr, and fails even if it would have exited with 0.
+    pub abort_on_stderr: bool,
+    /// Fails the run (with `EXIT_CODE_EMPTY_OUTPUT`) when the commands exit with 0 but write nothing to stdout.
+    /// The stdout is read through o-o to see it, whatever its destination is.
+    pub fail_on_empty_output: bool,
+    /// Makes sure the named stdout and stderr files exist after the run, created empty if nothing made them.
+    pub touch_output: bool,
+    /// Renames the named stdout file to this path after the pipeline succeeds, which replaces the file there atomically.
+    pub publish_as: Option<String>,
+    /// Sets the permissions of the named stdout and stderr files (and of the stdin file replaced by `=`) after the run.
+    pub chmod: Option<u32>,
+    /// Prints a digest of the bytes written to the stdout's destination to stderr, after the run.
+    pub stdout_hash: Option<HashAlgorithm>,
+    /// A JSON file of an array, whose elements are given to the pipeline as stdin, one per line (NDJSON).
+    pub stdin_json: Option<String>,
+    /// A file to which a copy of the bytes given to the pipeline as stdin is written.
+    pub stdin_tee: Option<String>,
+    /// Writes the output to the files of stdout and stderr (including `=`) and also through to the ones of o-o.
+    pub tee: bool,
+    /// The prefix of the name of the temporary file of `=` (or of --atomic-stdout).
+    pub temp_prefix: Option<String>,
+    /// The suffix of the name of the temporary file, e.g., an extension for a tool which inspects the name.
+    pub temp_suffix: Option<String>,
+    /// A header line written to the files appended to (`+file`), before the output. `%t` is replaced with the current time.
+    pub append_header: Option<String>,
+    /// Converts the line endings of stdin into the given one and writes them to stdout, by o-o itself without spawning the commands.
+    pub convert: Option<LineEnding>,
+    /// Skips the run (as a success) when the stdout file is newer than the stdin file, as a build step of make.
+    pub skip_if_fresh: bool,
+    /// Starts the commands with an empty environment, except for the variables of `env_passthrough` and `envs`.
+    pub clear_env: bool,
+    /// Variables inherited from the parent environment even with `clear_env`.
+    pub env_passthrough: Vec<String>,
+    /// The time by which the pipeline has to finish. When it passes, the pipeline is killed and `OOError::DeadlineExceeded` is returned.
+    pub deadline: Option<Instant>,
+    /// The pipeline is killed and `OOError::IdleTimeout` is returned when it writes no output (to either stdout or stderr)
+    /// for this long. The output is read through o-o to watch it, whatever its destinations are.
+    pub idle_timeout: Option<Duration>,
+    /// The signal sent to the pipeline when the deadline passes, instead of killing it at once.
+    pub timeout_signal: Option<i32>,
+    /// The number of times a failed pipeline is run again.
+    pub retries: usize,
+    /// The delay before each retry.
+    pub retry_delay: Duration,
+    /// The delay before each retry is randomized by up to ± this, so that processes retrying in lockstep get apart.
+    pub retry_jitter: Duration,
+    /// Connects the stdin of the pipeline to an empty stream, instead of inheriting the one of o-o, when no stdin file is given.
+    pub stdin_null: bool,
+    /// Changes the root directory of the commands before they are executed (Unix only, requires the root privilege).
+    /// The working directory is then resolved inside the new root.
+    pub chroot: Option<String>,
+    /// The `argv[0]` given to the first command of the pipeline, instead of its program name (Unix only).
+    pub argv0: Option<String>,
+    /// Descriptor numbers of o-o to attach the stdin, stdout and stderr of the pipeline to, in place of `-` (Unix only).
+    /// They are duplicated, so that the descriptors of o-o stay open.
+    pub inherited_fds: [Option<i32>; 3],
+    /// Draws a spinner with the elapsed time on stderr while the pipeline runs, when stderr is a terminal which the pipeline does not write to.
+    pub progress: bool,
+}
+
+/// Environment variables set (or removed) by `--force-color`, which are honored by many tools to emit colors even when not writing to a terminal.
+pub const FORCE_COLOR_ENVS: &[(&str, Option<&str>)] = &[("CLICOLOR_FORCE", Some("1")), ("FORCE_COLOR", Some("1")), ("NO_COLOR", None)];
+
+/// Formats an argument for display, quoting it as in shell when it contains special characters.
+pub fn quote_argument(arg: &str) -> String {
+    let is_plain = |c: char| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_plain) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+/// Formats the command lines of a pipeline for display, connecting them with ` | `.
+pub fn format_pipeline(commands: &[Vec<String>]) -> String {
+    commands.iter()
+        .map(|c| c.iter().map(|a| quote_argument(a)).collect::<Vec<String>>().join(" "))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+/// Prefix of the pseudo-token, at the beginning of a stage of a pipeline, specifying the working directory of the stage.
+pub const STAGE_DIRECTORY_PREFIX: &str = "cd:";
+
+/// Splits a leading `cd:DIR` pseudo-token off a stage of a pipeline.
+pub fn split_stage_directory(command: &[String]) -> std::result::Result<(Option<&str>, &[String]), OOError> {
+    let err = |message: String| Err(OOError::CLIError { message });
+
+    match command.first().and_then(|t| t.strip_prefix(STAGE_DIRECTORY_PREFIX)) {
+        Some("") => err(format!("directory not specified in {}DIR", STAGE_DIRECTORY_PREFIX)),
+        Some(_) if command.len() == 1 => err(format!("no command line after {}", command[0])),
+        Some(dir) => Ok((Some(dir), &command[1..])),
+        None => Ok((None, command)),
+    }
+}
+
+fn build_expression(spec: &PipelineSpec) -> Result<duct::Expression> {
+    let mut pipeline: Option<duct::Expression> = None;
+
+    let mut envs: Vec<(&str, Option<&str>)> = vec![];
+    if spec.force_color {
+        envs.extend_from_slice(FORCE_COLOR_ENVS); // before the user's ones, so that `-e`/`-u` can override them
+    }
+    envs.extend(spec.envs.iter().map(|(k, v)| (k.as_str(), v.as_deref())));
+    let passthrough_envs: Vec<(&str, std::ffi::OsString)> = spec.env_passthrough.iter()
+        .filter_map(|key| std::env::var_os(key).map(|value| (key.as_str(), value)))
+        .collect();
+    for (i, command) in spec.commands.iter().enumerate() {
+        let (stage_dir, command) = split_stage_directory(command)?;
+        let mut duct_cmd = cmd(&command[0], &command[1..]);
+        if let (0, Some(argv0)) = (i, spec.argv0.as_deref()) {
+            duct_cmd = duct_cmd.before_spawn(argv0_hook(argv0)?);
+        }
+
+        // A relative `cd:DIR` is resolved against the working directory given by -d, if any
+        let dir = match (spec.working_directory.as_deref(), stage_dir) {
+            (Some(wd), Some(sd)) => Some(Path::new(wd).join(sd)),
+            (wd, sd) => sd.or(wd).map(PathBuf::from),
+        };
+        if let Some(root) = spec.chroot.as_deref() {
+            duct_cmd = duct_cmd.before_spawn(chroot_hook(root, &jail_working_directory(dir.as_deref()))?);
+        } else if let Some(dir) = dir {
+            // As `cd` of shell does, PWD and OLDPWD are set for a script reading them, unless given by -e or -u
+            let pwd = std::path::absolute(&dir)?;
+            let oldpwd = std::env::current_dir()?;
+            for (key, value) in [("PWD", pwd), ("OLDPWD", oldpwd)] {
+                if !envs.iter().any(|&(k, _)| k == key) {
+                    duct_cmd = duct_cmd.env(key, value);
+                }
+            }
+            duct_cmd = duct_cmd.dir(dir);
+        }
+
+        for (key, value) in resolve_envs(&envs) {
+            duct_cmd = match value {
+                Some(value) => duct_cmd.env(key, value),
+                None => duct_cmd.env_remove(key),
+            };
+        }
+        if spec.clear_env {
+            // The outermost environment of a duct expression is applied first, so this has to wrap the ones above
+            duct_cmd = duct_cmd.full_env(passthrough_envs.iter().cloned());
+        }
+
+        if let Some(existing_pipeline) = pipeline {
+            pipeline = Some(existing_pipeline.pipe(duct_cmd));
+        } else {
+            pipeline = Some(duct_cmd);
+        }
+    }
+
+    pipeline.ok_or_else(|| anyhow::anyhow!("No command to execute"))
+}
+
+/// The working directory of a command run with `--chroot`: the given directory resolved from the new root, or the new root itself.
+pub fn jail_working_directory(dir: Option<&Path>) -> PathBuf {
+    match dir {
+        Some(dir) => Path::new("/").join(dir),
+        None => PathBuf::from("/"),
+    }
+}
+
+/// Makes a hook of duct calling chroot(2) and then chdir(2) in the child process, just before it executes the command.
+/// The working directory is changed in the hook, since the one set to the command is applied before the hook, outside the new root.
+#[cfg(unix)]
+fn chroot_hook(root: &str, dir: &Path) -> Result<impl Fn(&mut std::process::Command) -> io::Result<()> + Send + Sync + 'static> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::process::CommandExt;
+
+    // Prepared here, as allocating memory is not safe in the child between fork and exec
+    let root = CString::new(root)?;
+    let dir = CString::new(dir.as_os_str().as_bytes())?;
+    Ok(move |command: &mut std::process::Command| {
+        let (root, dir) = (root.clone(), dir.clone());
+        unsafe {
+            command.pre_exec(move || {
+                rustix::process::chroot(root.as_c_str())?;
+                rustix::process::chdir(dir.as_c_str())?;
+                Ok(())
+            });
+        }
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn chroot_hook(_root: &str, _dir: &Path) -> Result<fn(&mut std::process::Command) -> io::Result<()>> {
+    Err(OOError::CLIError { message: "option --chroot is not supported on this platform".to_string() }.into())
+}
+
+/// Makes a hook of duct giving the command the `argv[0]`, while the program to execute is still looked up by its name.
+#[cfg(unix)]
+fn argv0_hook(argv0: &str) -> Result<impl Fn(&mut std::process::Command) -> io::Result<()> + Send + Sync + 'static> {
+    use std::os::unix::process::CommandExt;
+
+    let argv0 = argv0.to_string();
+    Ok(move |command: &mut std::process::Command| {
+        command.arg0(&argv0);
+        Ok(())
+    })
+}
+
+#[cfg(not(unix))]
+fn argv0_hook(_argv0: &str) -> Result<fn(&mut std::process::Command) -> io::Result<()>> {
+    Err(OOError::CLIError { message: "option --argv0 is not supported on this platform".to_string() }.into())
+}
+
+/// Tells whether o-o has to read the output streams by itself, instead of handing the files to the subprocess.
+fn needs_capture(spec: &PipelineSpec) -> bool {
+    spec.interleave || spec.stream_labels.is_some() || spec.timestamp_format.is_some() || spec.pty || spec.strip_ansi_stdout || spec.strip_ansi_stderr
+        || spec.line_ending.is_some() || spec.stdout_on_error.is_some() || spec.stderr_on_error.is_some() || spec.ensure_trailing_newline
+        || spec.report_lines || spec.abort_on_stderr || spec.stdout_hash.is_some() || spec.tee || spec.fail_on_empty_output
+        || spec.idle_timeout.is_some()
+}
+
+/// Normalizes an exit status into 0–255, the range preserved by `std::process::exit` on Unix.
+/// A status out of the range (negative, or larger than 255 on platforms allowing it) is mapped to 255
+/// rather than truncated, so that a failure (e.g., 256) is never reported as a success.
+pub fn normalize_exit_code(code: i32) -> i32 {
+    if (0..=255).contains(&code) { code } else { 255 }
+}
+
+/// Returns the normalized exit status of a process. A process killed by a signal is given 128 + the signal number, as in shell.
+fn exit_code_of(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return normalize_exit_code(128 + signal);
+        }
+    }
+    normalize_exit_code(status.code().unwrap_or(1))
+}
+
+/// Flushes the contents of a written file to the storage device.
+fn sync_file(path: &Path) -> Result<()> {
+    let file = OpenOptions::new().append(true).open(path)
+        .with_context(|| format!("Failed to sync file: {}", path.display()))?;
+    file.sync_all()?;
+    Ok(())
+}
+
+/// Flushes the directory entry of a file, e.g., after a rename, to the storage device.
+#[cfg(unix)]
+fn sync_parent_directory(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_parent_directory(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Tells whether an fd argument names a file, rather than being one of the special values `-`, `=` and `.`.
+fn is_named_file(fd: &str) -> bool {
+    !(fd == "-" || fd == "=" || fd == ".")
+}
+
+/// Tells whether the stdout file is newer than the stdin file. False unless both are existing files.
+fn is_output_fresh(fds: &[&str]) -> bool {
+    if !(is_named_file(fds[0]) && is_named_file(fds[1])) {
+        return false;
+    }
+    let modified = |fd: &str| fs::metadata(split_append_flag(fd).0).and_then(|m| m.modified()).ok();
+    match (modified(fds[0]), modified(fds[1])) {
+        (Some(input), Some(output)) => output > input,
+        _ => false,
+    }
+}
+
+/// A small PRNG (xorshift64*) for the retry jitter, which does not need to be of cryptographic quality.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn with_seed(seed: u64) -> Rng {
+        Rng(seed.max(1)) // the state of xorshift must not be zero
+    }
+
+    /// Seeds with the process id and the current time, so that concurrent o-o processes get different sequences.
+    pub fn from_process() -> Rng {
+        let nanos = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Rng::with_seed(nanos ^ (u64::from(std::process::id()) << 32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+}
+
+/// Randomizes the delay uniformly within ±jitter (in milliseconds), not going below zero.
+pub fn jittered_delay(delay: Duration, jitter: Duration, rng: &mut Rng) -> Duration {
+    let jitter_ms = jitter.as_millis() as u64;
+    if jitter_ms == 0 {
+        return delay;
+    }
+    let offset = rng.next_u64() % (2 * jitter_ms + 1);
+    (delay + Duration::from_millis(offset)).saturating_sub(jitter)
+}
+
+/// Replaces the stdin file of the fds with its canonical path, in which every symbolic link is resolved.
+fn dereference_stdin(fds: &[String]) -> Result<Vec<String>> {
+    let path = fs::canonicalize(&fds[0]).map_err(|e| stdin_file_error(&fds[0], &e))?;
+    let mut fds = fds.to_vec();
+    fds[0] = path.to_string_lossy().into_owned();
+    Ok(fds)
+}
+
+/// The outcome of running a pipeline.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RunOutcome {
+    /// The exit status of the pipeline, as o-o exits with.
+    pub exit_code: i32,
+    /// The exit status of each stage of the pipe, from left to right. `None` for a stage whose status could not be
+    /// observed, e.g., when o-o killed the pipeline. Empty when no command was run (e.g., by --skip-if-fresh).
+    pub stage_exit_codes: Vec<Option<i32>>,
+}
+
+/// Runs the pipeline, running it again up to `spec.retries` times while it fails.
+pub fn run_pipeline(spec: &PipelineSpec) -> Result<i32> {
+    run_pipeline_outcome(spec).map(|outcome| outcome.exit_code)
+}
+
+/// Runs the pipeline as `run_pipeline` does, returning the exit status of each stage as well as that of the pipeline.
+/// With retries, the outcome is that of the last run.
+pub fn run_pipeline_outcome(spec: &PipelineSpec) -> Result<RunOutcome> {
+    let run_once = || run_pipeline_once(spec).map_err(|e| match spec.chroot.as_deref() {
+        Some(root) if is_permission_error(&e) => e.context(format!("cannot chroot to {} (the root privilege is required)", root)),
+        _ => e,
+    });
+
+    let mut rng = Rng::from_process();
+    let mut outcome = run_once()?;
+    for _ in 0..spec.retries {
+        if outcome.exit_code == 0 {
+            break;
+        }
+        sleep(jittered_delay(spec.retry_delay, spec.retry_jitter, &mut rng));
+        outcome = run_once()?;
+    }
+    Ok(outcome)
+}
+
+fn is_permission_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| cause.downcast_ref::<io::Error>().is_some_and(|e| e.kind() == io::ErrorKind::PermissionDenied))
+}
+
+/// Returns the absolute path of the directory containing the stdin file (option --chdir-to-input).
+fn input_directory(stdin: &str) -> Result<String> {
+    let path = std::path::absolute(stdin).with_context(|| format!("Failed to resolve stdin file: {}", stdin))?;
+    let dir = path.parent().unwrap_or(&path); // only the root has no parent
+    Ok(dir.to_string_lossy().into_owned())
+}
+
+fn run_pipeline_once(spec: &PipelineSpec) -> Result<RunOutcome> {
+    let in_place;
+    let spec = if spec.fds[0] == "=" {
+        // `= FILE` is `FILE =`, reading the file named as stdout and replacing it with the output
+        in_place = PipelineSpec { fds: vec![spec.fds[1].clone(), "=".to_string(), spec.fds[2].clone()], ..spec.clone() };
+        &in_place
+    } else {
+        spec
+    };
+    let dereferenced;
+    let spec = if spec.dereference_stdin && spec.fds[0] != "-" && !spec.stdin_glob {
+        dereferenced = PipelineSpec { fds: dereference_stdin(&spec.fds)?, ..spec.clone() };
+        &dereferenced
+    } else {
+        spec
+    };
+    let in_input_directory;
+    let spec = if spec.chdir_to_input && spec.working_directory.is_none() && spec.fds[0] != "-" && spec.stdin_json.is_none()
+        && !(spec.stdin_glob && has_glob_metacharacters(&spec.fds[0])) {
+        in_input_directory = PipelineSpec { working_directory: Some(input_directory(&spec.fds[0])?), ..spec.clone() };
+        &in_input_directory
+    } else {
+        spec
+    };
+    let mut fds: Vec<&str> = spec.fds.iter().map(|s| s.as_str()).collect();
+    let tempdir_placeholder = spec.tempdir_placeholder.as_deref();
+
+    if spec.skip_if_fresh && !spec.stdin_glob && is_output_fresh(&fds) {
+        return Ok(RunOutcome::default());
+    }
+    if spec.deadline.is_some_and(|d| Instant::now() >= d) {
+        return Err(OOError::DeadlineExceeded.into());
+    }
+
+    let stdin = open_stdin(spec)?;
+
+    let mut temp_output = None;
+    if fds[1] == "=" {
+        temp_output = Some(TempOutput::create(&tempdir_placeholder, spec)?);
+    } else if spec.atomic_stdout && is_named_file(fds[1]) {
+        // From here on, the stdout is written as that of `=`, and the temporary file replaces the named file
+        temp_output = Some(TempOutput::create_for(fds[1], spec)?);
+        fds[1] = "=";
+    }
+
+    if let Some(line_ending) = spec.convert {
+        convert_line_endings(line_ending, stdin, spec, &fds, temp_output.as_ref())?;
+        return finish_pipeline(spec, &fds, true, temp_output).map(|()| RunOutcome::default());
+    }
+
+    let mut final_pipeline = build_expression(spec)?;
+    if let Some(stdin) = stdin {
+        final_pipeline = stdin.redirect(final_pipeline);
+    } else if let Some(fd) = spec.inherited_fds[0] {
+        final_pipeline = final_pipeline.stdin_file(dup_inherited_fd(fd)?);
+    } else if spec.stdin_null {
+        final_pipeline = final_pipeline.stdin_null();
+    }
+
+    let progress = Progress::start(spec, &fds);
+    let (status, rejected_exit_code, stage_exit_codes) = if needs_capture(spec) {
+        run_captured(final_pipeline, spec, &fds, temp_output.as_ref())?
+    } else {
+        match fds[1] {
+            "=" => {
+                final_pipeline = final_pipeline.stdout_file(temp_output.as_ref().unwrap().writer()?);
+            }
+            "." => {
+                final_pipeline = final_pipeline.stdout_null();
+            }
+            "-" => {
+                if let Some(fd) = spec.inherited_fds[1] {
+                    final_pipeline = final_pipeline.stdout_file(dup_inherited_fd(fd)?);
+                }
+            }
+            _ if fds[2] == "=" => {
+                // Stderr gets a handle of its own to the same file. Both handles append, so that each write
+                // lands at the end of the file and neither stream overwrites the other
+                drop(open_output_file(fds[1], spec.append_header.as_deref())?);
+                let path = split_append_flag(fds[1]).0;
+                let open_appending = || OpenOptions::new().append(true).open(path).with_context(|| format!("Failed to open file: {}", path));
+                final_pipeline = final_pipeline.stdout_file(open_appending()?).stderr_file(open_appending()?);
+            }
+            _ => {
+                let file = open_output_file(fds[1], spec.append_header.as_deref())?;
+                final_pipeline = final_pipeline.stdout_file(file);
+            }
+        }
+
+        match fds[2] {
+            "=" if is_named_file(fds[1]) => {
+                // already redirected to the stdout's file
+            }
+            "=" if fds[1] == "=" => {
+                // A handle of its own to the temporary file, which shares the file offset with that of stdout.
+                // The redirection of stderr to stdout would be applied before that of stdout to the temporary file
+                final_pipeline = final_pipeline.stderr_file(temp_output.as_ref().unwrap().writer()?);
+            }
+            "=" => {
+                final_pipeline = final_pipeline.stderr_to_stdout();
+            }
+            "." => {
+                final_pipeline = final_pipeline.stderr_null();
+            }
+            "-" => {
+                if let Some(fd) = spec.inherited_fds[2] {
+                    final_pipeline = final_pipeline.stderr_file(dup_inherited_fd(fd)?);
+                }
+            }
+            _ => {
+                let file = open_output_file(fds[2], spec.append_header.as_deref())?;
+                final_pipeline = final_pipeline.stderr_file(file);
+            }
+        }
+
+        let handle = final_pipeline.unchecked().start()?;
+        let mut stages = StageWatcher::new(&handle);
+        let status = match spec.deadline {
+            Some(deadline) => wait_until(&handle, &mut stages, deadline, spec.timeout_signal)?,
+            None => {
+                stages.observe(true);
+                handle.wait()?.status
+            }
+        };
+        drop(final_pipeline); // release the stdin file held by the expression, before it is replaced by `=`
+        (status, None, stages.exit_codes())
+    };
+    drop(progress);
+
+    finish_pipeline(spec, &fds, status.success() && rejected_exit_code.is_none(), temp_output)?;
+    let exit_code = match exit_code_of(status) {
+        0 => rejected_exit_code.unwrap_or(0),
+        code => code,
+    };
+    Ok(RunOutcome { exit_code, stage_exit_codes })
+}
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+const PROGRESS_SPINNER: [char; 4] = ['|', '/', '-', '\\'];
+
+/// The spinner of --progress, redrawn in place on stderr by a thread of its own until the value is dropped, which erases it.
+struct Progress {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Progress {
+    /// Starts drawing, unless the spinner would leak into a redirected stderr or get mixed with the output of the commands
+    /// on the terminal, in which case `None` is returned.
+    fn start(spec: &PipelineSpec, fds: &[&str]) -> Option<Progress> {
+        if !spec.progress || !io::stderr().is_terminal() {
+            return None;
+        }
+        // With --tee, the output to the files is written to the streams of o-o, too
+        let passed_through = |fd: &str| fd == "-" || spec.tee && fd != ".";
+        let stdout_to_terminal = passed_through(fds[1]) && spec.inherited_fds[1].is_none() && io::stdout().is_terminal();
+        let stderr_to_terminal = match fds[2] {
+            "=" => stdout_to_terminal,
+            fd => passed_through(fd) && spec.inherited_fds[2].is_none(),
+        };
+        if stdout_to_terminal || stderr_to_terminal {
+            return None;
+        }
+
+        let (stop, stopped) = mpsc::channel::<()>();
+        let started = Instant::now();
+        let thread = thread::spawn(move || {
+            let mut stderr = io::stderr();
+            for spinner in PROGRESS_SPINNER.iter().cycle() {
+                let _ = write!(stderr, "\r{} {:.1}s", spinner, started.elapsed().as_secs_f64());
+                let _ = stderr.flush();
+                if stopped.recv_timeout(PROGRESS_INTERVAL) != Err(mpsc::RecvTimeoutError::Timeout) {
+                    break;
+                }
+            }
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        });
+        Some(Progress { stop: Some(stop), thread: Some(thread) })
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Observes the exit statuses of the stages of a started pipeline, one per process of the pipe. The processes are
+/// peeked at without being reaped, which is left to duct, so this has to be done before the handle is waited.
+struct StageWatcher {
+    pids: Vec<u32>,
+    exit_codes: Vec<Option<Option<i32>>>, // None while the stage is running; Some(None) when its status is unknown
+}
+
+impl StageWatcher {
+    fn new(handle: &duct::Handle) -> StageWatcher {
+        let pids = handle.pids();
+        let exit_codes = vec![None; pids.len()];
+        StageWatcher { pids, exit_codes }
+    }
+
+    /// Observes the stages which have exited, waiting for all the stages to exit with `block`.
+    /// Returns whether all the stages have been observed.
+    fn observe(&mut self, block: bool) -> bool {
+        for (pid, exit_code) in self.pids.iter().zip(self.exit_codes.iter_mut()) {
+            if exit_code.is_none() {
+                *exit_code = match peek_exit_code(*pid, block) {
+                    Ok(None) => None,
+                    Ok(Some(code)) => Some(Some(code)),
+                    Err(_) => Some(None), // e.g., already reaped by killing the pipeline
+                };
+            }
+        }
+        self.exit_codes.iter().all(Option::is_some)
+    }
+
+    fn exit_codes(mut self) -> Vec<Option<i32>> {
+        self.observe(false);
+        self.exit_codes.into_iter().map(Option::flatten).collect()
+    }
+}
+
+/// Returns the normalized exit status of the child process if it has exited, leaving the process waitable.
+#[cfg(unix)]
+fn peek_exit_code(pid: u32, block: bool) -> io::Result<Option<i32>> {
+    use rustix::io::Errno;
+    use rustix::process::{waitid, Pid, WaitId, WaitIdOptions};
+
+    let Some(pid) = Pid::from_raw(pid as i32) else {
+        return Err(io::Error::from(io::ErrorKind::InvalidInput));
+    };
+    let mut options = WaitIdOptions::EXITED | WaitIdOptions::NOWAIT;
+    if !block {
+        options |= WaitIdOptions::NOHANG;
+    }
+    let status = loop {
+        match waitid(WaitId::Pid(pid), options) {
+            Err(Errno::INTR) => continue,
+            r => break r?,
+        }
+    };
+    Ok(status.map(|status| match (status.exit_status(), status.terminating_signal()) {
+        (_, Some(signal)) => normalize_exit_code(128 + signal),
+        (code, None) => normalize_exit_code(code.unwrap_or(1)),
+    }))
+}
+
+#[cfg(not(unix))]
+fn peek_exit_code(_pid: u32, _block: bool) -> io::Result<Option<i32>> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// How long the pipeline is given to exit after the signal of --signal-on-timeout, before it is killed.
+pub const TIMEOUT_SIGNAL_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+/// Waits for the pipeline to finish, killing it when the deadline passes first.
+/// With a signal, the signal is sent to the processes instead, and they are killed only when still running after
+/// `TIMEOUT_SIGNAL_GRACE_PERIOD`.
+fn wait_until(handle: &duct::Handle, stages: &mut StageWatcher, deadline: Instant, signal: Option<i32>) -> Result<ExitStatus> {
+    loop {
+        // The handle is waited only after all the stages are observed, since waiting reaps the stages exited
+        if stages.observe(false) {
+            if let Some(output) = handle.try_wait()? {
+                return Ok(output.status);
+            }
+        }
+        if Instant::now() >= deadline {
+            if let Some(signal) = signal {
+                send_signal(handle, signal)?;
+                let grace_end = Instant::now() + TIMEOUT_SIGNAL_GRACE_PERIOD;
+                while handle.try_wait()?.is_none() && Instant::now() < grace_end {
+                    sleep(DEFAULT_POLL_INTERVAL);
+                }
+            }
+            handle.kill()?;
+            return Err(OOError::DeadlineExceeded.into());
+        }
+        sleep(DEFAULT_POLL_INTERVAL);
+    }
+}
+
+/// Sends the signal to the processes of the pipeline which are still running.
+#[cfg(unix)]
+fn send_signal(handle: &duct::Handle, signal: i32) -> Result<()> {
+    use rustix::process::{kill_process, Pid, Signal};
+
+    let Some(signal) = Signal::from_named_raw(signal) else {
+        return Err(OOError::CLIError { message: format!("invalid signal: {}", signal) }.into());
+    };
+    for pid in handle.pids() {
+        if let Some(pid) = Pid::from_raw(pid as i32) {
+            let _ = kill_process(pid, signal); // the process may have exited already
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn send_signal(_handle: &duct::Handle, _signal: i32) -> Result<()> {
+    Err(OOError::CLIError { message: "option --signal-on-timeout is not supported on this platform".to_string() }.into())
+}
+
+/// Parses a permission mode in octal, as `644`, `0644` or `0o644`, up to `7777`.
+pub fn parse_octal_mode(s: &str) -> Result<u32> {
+    let invalid = || -> anyhow::Error { OOError::CLIError { message: format!("invalid octal mode: {}", s) }.into() };
+    let digits = s.strip_prefix("0o").unwrap_or(s);
+    if digits.is_empty() || !digits.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        return Err(invalid());
+    }
+    match u32::from_str_radix(digits, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(unix)]
+fn set_file_mode(path: &str, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode)).with_context(|| format!("Failed to change the mode of file: {}", path))
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_path: &str, _mode: u32) -> Result<()> {
+    Err(OOError::CLIError { message: "option --chmod is not supported on this platform".to_string() }.into())
+}
+
+/// Sets the file mode creation mask of o-o, which the subprocesses inherit.
+#[cfg(unix)]
+pub fn set_umask(mask: u32) -> Result<()> {
+    rustix::process::umask(rustix::fs::Mode::from_raw_mode(mask));
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_umask(_mask: u32) -> Result<()> {
+    Err(OOError::CLIError { message: "option --umask is not supported on this platform".to_string() }.into())
+}
+
+const LOCK_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An exclusive advisory lock on a file (option --lock-file), which is released when the value is dropped,
+/// or by the OS when o-o exits in any other way.
+pub struct FileLock {
+    _file: File,
+}
+
+/// Locks the file, created if missing, waiting for another o-o holding the lock to release it.
+/// With a timeout, gives up once it passes.
+pub fn lock_file(path: &str, timeout: Option<Duration>) -> Result<FileLock> {
+    let file = OpenOptions::new().write(true).create(true).truncate(false).open(path)
+        .with_context(|| format!("Failed to open lock file: {}", path))?;
+    match timeout {
+        None => file.lock().with_context(|| format!("Failed to lock file: {}", path))?,
+        Some(timeout) => {
+            let give_up = Instant::now() + timeout;
+            loop {
+                match file.try_lock() {
+                    Ok(()) => break,
+                    Err(fs::TryLockError::WouldBlock) => {
+                        let now = Instant::now();
+                        if now >= give_up {
+                            anyhow::bail!("timed out waiting for the lock of {}", path);
+                        }
+                        sleep(LOCK_POLL_INTERVAL.min(give_up - now));
+                    }
+                    Err(fs::TryLockError::Error(e)) => return Err(e).with_context(|| format!("Failed to lock file: {}", path)),
+                }
+            }
+        }
+    }
+    Ok(FileLock { _file: file })
+}
+
+/// Parses a signal given by a name (e.g., `TERM` or `SIGTERM`, in any case) or a number, into the signal number.
+#[cfg(unix)]
+pub fn parse_signal(name: &str) -> Option<i32> {
+    use rustix::process::Signal;
+
+    if let Ok(number) = name.parse::<i32>() {
+        return Signal::from_named_raw(number).map(Signal::as_raw);
+    }
+    let upper = name.to_ascii_uppercase();
+    let signal = match upper.strip_prefix("SIG").unwrap_or(&upper) {
+        "HUP" => Signal::HUP,
+        "INT" => Signal::INT,
+        "QUIT" => Signal::QUIT,
+        "ABRT" => Signal::ABORT,
+        "KILL" => Signal::KILL,
+        "USR1" => Signal::USR1,
+        "USR2" => Signal::USR2,
+        "PIPE" => Signal::PIPE,
+        "ALRM" => Signal::ALARM,
+        "TERM" => Signal::TERM,
+        "CONT" => Signal::CONT,
+        "STOP" => Signal::STOP,
+        "TSTP" => Signal::TSTP,
+        "WINCH" => Signal::WINCH,
+        _ => return None,
+    };
+    Some(signal.as_raw())
+}
+
+#[cfg(not(unix))]
+pub fn parse_signal(_name: &str) -> Option<i32> {
+    None
+}
+
+/// Receives the next chunk read from the pipeline. `None` means the readers are done, or the deadline passed.
+fn recv_chunk(rx: &mpsc::Receiver<(Stream, Vec<u8>)>, deadline: Option<Instant>) -> Option<(Stream, Vec<u8>)> {
+    match deadline {
+        Some(deadline) => rx.recv_timeout(deadline.saturating_duration_since(Instant::now())).ok(),
+        None => rx.recv().ok(),
+    }
+}
+
+/// Duplicates a descriptor inherited by o-o (e.g., a socket passed by systemd), to attach it to a subprocess.
+#[cfg(unix)]
+fn dup_inherited_fd(fd: i32) -> Result<File> {
+    use std::os::fd::BorrowedFd;
+
+    // The descriptor is only borrowed for dup(2), so it is not closed by the returned file
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned = rustix::io::dup(borrowed).with_context(|| format!("Failed to use descriptor: {}", fd))?;
+    Ok(File::from(owned))
+}
+
+#[cfg(not(unix))]
+fn dup_inherited_fd(_fd: i32) -> Result<File> {
+    Err(OOError::CLIError { message: "options --stdin-fd, --stdout-fd and --stderr-fd are not supported on this platform".to_string() }.into())
+}
+
+/// The destination of a stream not redirected (`-`): an inherited descriptor if given, otherwise the same stream of o-o.
+fn passthrough_writer(stream: Stream, inherited_fd: Option<i32>) -> Result<Box<dyn Write>> {
+    Ok(match (inherited_fd, stream) {
+        (Some(fd), _) => Box::new(dup_inherited_fd(fd)?),
+        (None, Stream::Stdout) => Box::new(io::stdout()),
+        (None, Stream::Stderr) => Box::new(io::stderr()),
+    })
+}
+
+/// Syncs the written files, and replaces the stdin file with the temporary file of `=` when the pipeline succeeded (or `force_overwrite`).
+/// Before the swap, the temporary file is synced, and when o-o copied the output to it, its length is checked against
+/// the bytes copied, so that a short write is reported and the stdin file is kept as it is, rather than truncated.
+fn finish_pipeline(spec: &PipelineSpec, fds: &[&str], success: bool, temp_output: Option<TempOutput>) -> Result<()> {
+    if !spec.no_sync {
+        for fd in &fds[1..] {
+            if !(*fd == "-" || *fd == "=" || *fd == ".") {
+                sync_file(Path::new(split_append_flag(fd).0))?;
+            }
+        }
+    }
+
+    if success || spec.force_overwrite {
+        if let Some(temp) = temp_output {
+            // The subprocess has already run, so a failure here is reported as such rather than as a generic error
+            let target = temp.target.as_deref().unwrap_or(fds[0]);
+            let replace_error = |e: io::Error| OOError::ReplaceFile { path: target.to_string(), reason: e.to_string() };
+            if !spec.no_sync {
+                temp.file.sync_all().map_err(replace_error)?;
+            }
+            let length = temp.file.metadata().map_err(replace_error)?.len();
+            drop(temp.file);
+            // Without a count, the subprocess wrote to the file by itself, and the length cannot be checked
+            if let Some(copied) = temp.copied.get().map(|c| c.load(Ordering::SeqCst)) {
+                if length != copied {
+                    let _ = fs::remove_file(&temp.path);
+                    return Err(OOError::ReplaceFile {
+                        path: target.to_string(),
+                        reason: format!("size mismatch: {} bytes written, but {} bytes found", copied, length),
+                    }.into());
+                }
+            }
+            if temp.target.is_none() {
+                fs::remove_file(target).map_err(replace_error)?;
+            } // otherwise, the rename replaces the file atomically, so that it is never seen half-written
+            fs::rename(&temp.path, target).map_err(replace_error)?;
+            if !spec.no_sync {
+                sync_parent_directory(Path::new(target))?;
+            }
+        }
+    } else if let Some(temp) = temp_output {
+        drop(temp.file);
+        let _ = fs::remove_file(&temp.path); // the output is not used
+    }
+
+    if spec.touch_output {
+        // The original fds, to include the stdout file of --atomic-stdout left absent by a failure
+        for fd in spec.fds[1..].iter().filter(|fd| is_named_file(fd)) {
+            let path = split_append_flag(fd).0;
+            OpenOptions::new().create(true).append(true).open(path).with_context(|| format!("Failed to create file: {}", path))?;
+        }
+    }
+
+    if let Some(mode) = spec.chmod {
+        let replaced = if spec.fds[1] == "=" { Some(spec.fds[0].as_str()) } else { None };
+        let named = spec.fds[1..].iter().filter(|fd| is_named_file(fd)).map(|fd| split_append_flag(fd).0);
+        for path in replaced.into_iter().chain(named) {
+            if Path::new(path).exists() { // a file of --atomic-stdout is not there after a failure
+                set_file_mode(path, mode)?;
+            }
+        }
+    }
+
+    if let (true, Some(publish_as)) = (success, spec.publish_as.as_deref()) {
+        let publish_error = |e: io::Error| OOError::ReplaceFile { path: publish_as.to_string(), reason: e.to_string() };
+        fs::rename(&spec.fds[1], publish_as).map_err(publish_error)?;
+        if !spec.no_sync {
+            sync_parent_directory(Path::new(publish_as))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Copies stdin to stdout converting the line endings (option --convert), with the redirections of the fds.
+/// Nothing is written to stderr, but its file is created as when commands are run.
+fn convert_line_endings(line_ending: LineEnding, stdin: Option<StdinSource>, spec: &PipelineSpec, fds: &[&str], temp_output: Option<&TempOutput>) -> Result<()> {
+    let mut reader: Box<dyn Read> = match stdin {
+        Some(stdin) => stdin.into_reader(),
+        None if spec.inherited_fds[0].is_some() => Box::new(dup_inherited_fd(spec.inherited_fds[0].unwrap())?),
+        None if spec.stdin_null => Box::new(io::empty()),
+        None => Box::new(io::stdin()),
+    };
+    let mut writer: Box<dyn Write> = match fds[1] {
+        "=" => Box::new(temp_output.unwrap().counting_writer()?),
+        "." => Box::new(io::sink()),
+        "-" => passthrough_writer(Stream::Stdout, spec.inherited_fds[1])?,
+        path => Box::new(open_output_file(path, spec.append_header.as_deref())?),
+    };
+    if !matches!(fds[2], "=" | "." | "-") {
+        open_output_file(fds[2], spec.append_header.as_deref())?;
+    }
+
+    let mut converter = LineEndingConverter::new(line_ending);
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&converter.convert(&buf[..n]))?;
+    }
+    writer.write_all(&converter.finish())?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+fn spawn_reader<R: Read + Send + 'static>(mut reader: R, stream: Stream, tx: mpsc::Sender<(Stream, Vec<u8>)>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => {
+                    if tx.send((stream, buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// A running pipeline whose stdout and stderr are read by reader threads.
+/// Both readers feed a single channel, so the chunks are received in the order of their arrival.
+struct CapturedRun {
+    handle: duct::Handle,
+    rx: mpsc::Receiver<(Stream, Vec<u8>)>,
+    readers: Vec<thread::JoinHandle<()>>,
+}
+
+/// Opens a pseudo-terminal, returning its master and slave.
+/// The output processing of the terminal is disabled, so that `\n` is not translated into `\r\n`.
+#[cfg(unix)]
+fn open_pty() -> Result<(File, File)> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use rustix::pty::{grantpt, openpt, ptsname, unlockpt, OpenptFlags};
+    use rustix::termios::{tcgetattr, tcsetattr, OptionalActions, OutputModes};
+
+    let master = openpt(OpenptFlags::RDWR | OpenptFlags::NOCTTY)?;
+    grantpt(&master)?;
+    unlockpt(&master)?;
+    let name = ptsname(&master, vec![])?;
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(rustix::fs::OFlags::NOCTTY.bits() as i32)
+        .open(name.to_str()?)?;
+
+    let mut termios = tcgetattr(&slave)?;
+    termios.output_modes.remove(OutputModes::OPOST);
+    tcsetattr(&slave, OptionalActions::Now, &termios)?;
+
+    Ok((File::from(master), slave))
+}
+
+#[cfg(not(unix))]
+fn open_pty() -> Result<(File, File)> {
+    Err(OOError::CLIError { message: "option --pty is not supported on this platform".to_string() }.into())
+}
+
+/// Starts the pipeline with its stdout and stderr connected to pipes read by o-o.
+/// With `pty`, the stdout is connected to a pseudo-terminal instead, so that the subprocess regards it as interactive.
+fn start_captured(pipeline: duct::Expression, pty: bool) -> Result<CapturedRun> {
+    let (stderr_reader, stderr_writer) = os_pipe::pipe()?;
+    let (tx, rx) = mpsc::channel();
+    let (pipeline, stdout_reader): (_, Box<dyn Read + Send>) = if pty {
+        let (master, slave) = open_pty()?;
+        (pipeline.stdout_file(slave), Box::new(master))
+    } else {
+        let (stdout_reader, stdout_writer) = os_pipe::pipe()?;
+        (pipeline.stdout_file(stdout_writer), Box::new(stdout_reader))
+    };
+    let pipeline = pipeline.stderr_file(stderr_writer).unchecked();
+    let handle = pipeline.start()?;
+    drop(pipeline); // close the write ends held by this process, so that the readers see EOF
+
+    // Reading the master of a pseudo-terminal fails (EIO) once the slave is closed, which is also treated as EOF
+    let readers = vec![
+        spawn_reader(stdout_reader, Stream::Stdout, tx.clone()),
+        spawn_reader(stderr_reader, Stream::Stderr, tx),
+    ];
+
+    Ok(CapturedRun { handle, rx, readers })
+}
+
+/// A transformation applied to a captured stream, chunk by chunk.
+trait StreamFilter {
+    fn filter(&mut self, chunk: &[u8]) -> Vec<u8>;
+
+    /// Returns the bytes held back by the filter, at the end of the stream.
+    fn finish(&mut self) -> Vec<u8> {
+        vec![]
+    }
+}
+
+/// Prefixes each line with a string, which is made when the first byte of the line arrives.
+/// Lines are emitted only when complete (or at the end of the stream), so that lines of two streams are not mixed up.
+struct LinePrefixer {
+    make_prefix: Box<dyn Fn() -> Vec<u8>>,
+    pending: Vec<u8>,
+}
+
+impl LinePrefixer {
+    fn new(make_prefix: Box<dyn Fn() -> Vec<u8>>) -> LinePrefixer {
+        LinePrefixer { make_prefix, pending: vec![] }
+    }
+}
+
+impl StreamFilter for LinePrefixer {
+    fn filter(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        for &b in chunk {
+            if self.pending.is_empty() {
+                self.pending = (self.make_prefix)();
+            }
+            self.pending.push(b);
+            if b == b'\n' {
+                out.append(&mut self.pending);
+            }
+        }
+        out
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AnsiState {
+    Ground,
+    /// After ESC.
+    Escape,
+    /// After ESC and intermediate bytes, e.g., `ESC (` of a charset designation.
+    EscapeIntermediate,
+    /// In a CSI sequence (`ESC [` ... final byte).
+    Csi,
+    /// In an OSC string (`ESC ]` ... BEL or `ESC \`).
+    Osc,
+    /// After ESC in an OSC string.
+    OscEscape,
+}
+
+/// Removes ANSI escape sequences (CSI sequences such as colors and cursor moves, OSC strings, and other ESC sequences).
+/// The state is kept between chunks, so that a sequence split across chunks is also removed.
+pub struct AnsiStripper {
+    state: AnsiState,
+}
+
+impl Default for AnsiStripper {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AnsiStripper {
+    pub fn new() -> AnsiStripper {
+        AnsiStripper { state: AnsiState::Ground }
+    }
+
+    pub fn strip(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(chunk.len());
+        for &b in chunk {
+            self.state = match (self.state, b) {
+                (AnsiState::Ground, 0x1b) => AnsiState::Escape,
+                (AnsiState::Ground, _) => {
+                    out.push(b);
+                    AnsiState::Ground
+                }
+                (AnsiState::Escape, b'[') => AnsiState::Csi,
+                (AnsiState::Escape, b']') => AnsiState::Osc,
+                (AnsiState::Escape, 0x20..=0x2f) | (AnsiState::EscapeIntermediate, 0x20..=0x2f) => AnsiState::EscapeIntermediate,
+                (AnsiState::Escape, _) | (AnsiState::EscapeIntermediate, _) => AnsiState::Ground,
+                (AnsiState::Csi, 0x40..=0x7e) => AnsiState::Ground,
+                (AnsiState::Csi, _) => AnsiState::Csi,
+                (AnsiState::Osc, 0x07) => AnsiState::Ground,
+                (AnsiState::Osc, 0x1b) => AnsiState::OscEscape,
+                (AnsiState::Osc, _) => AnsiState::Osc,
+                (AnsiState::OscEscape, b'\\') => AnsiState::Ground,
+                (AnsiState::OscEscape, _) => AnsiState::Osc,
+            };
+        }
+        out
+    }
+}
+
+impl StreamFilter for AnsiStripper {
+    fn filter(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.strip(chunk)
+    }
+}
+
+/// Line ending of the output files (`--lf`, `--crlf`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+/// Converts both `\n` and `\r\n` into the given line ending. A lone `\r` is left as it is.
+/// A `\r` at the end of a chunk is held back until the next chunk tells whether a `\n` follows.
+pub struct LineEndingConverter {
+    line_ending: LineEnding,
+    pending_cr: bool,
+}
+
+impl LineEndingConverter {
+    pub fn new(line_ending: LineEnding) -> LineEndingConverter {
+        LineEndingConverter { line_ending, pending_cr: false }
+    }
+
+    pub fn convert(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let newline: &[u8] = match self.line_ending {
+            LineEnding::Lf => b"\n",
+            LineEnding::Crlf => b"\r\n",
+        };
+        let mut out = Vec::with_capacity(chunk.len());
+        for &b in chunk {
+            match b {
+                b'\n' => {
+                    out.extend_from_slice(newline);
+                    self.pending_cr = false;
+                }
+                _ => {
+                    if self.pending_cr {
+                        out.push(b'\r');
+                    }
+                    self.pending_cr = b == b'\r';
+                    if !self.pending_cr {
+                        out.push(b);
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+impl StreamFilter for LineEndingConverter {
+    fn filter(&mut self, chunk: &[u8]) -> Vec<u8> {
+        self.convert(chunk)
+    }
+
+    fn finish(&mut self) -> Vec<u8> {
+        if std::mem::take(&mut self.pending_cr) { vec![b'\r'] } else { vec![] }
+    }
+}
+
+/// The default format of `--prefix-timestamps`, which is RFC 3339 with milliseconds.
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+/// Tells whether a strftime-like format string is valid.
+pub fn is_valid_timestamp_format(format: &str) -> bool {
+    !chrono::format::StrftimeItems::new(format).any(|item| matches!(item, chrono::format::Item::Error))
+}
+
+pub fn format_timestamp(format: &str) -> String {
+    chrono::Local::now().format(format).to_string()
+}
+
+/// Returns the filters of a stream. `destination` is the file name the stream is finally written to
+/// (for stderr of `=`, the stdout's one).
+fn stream_filters(spec: &PipelineSpec, stream: Stream, destination: &str) -> Vec<Box<dyn StreamFilter>> {
+    let mut filters: Vec<Box<dyn StreamFilter>> = vec![];
+    let strip_ansi = match stream {
+        Stream::Stdout => spec.strip_ansi_stdout,
+        Stream::Stderr => spec.strip_ansi_stderr,
+    };
+    if strip_ansi {
+        filters.push(Box::new(AnsiStripper::new()));
+    }
+    if let Some((out_label, err_label)) = &spec.stream_labels {
+        let prefix = format!("{}: ", if stream == Stream::Stdout { out_label } else { err_label }).into_bytes();
+        filters.push(Box::new(LinePrefixer::new(Box::new(move || prefix.clone()))));
+    }
+    if let Some(format) = &spec.timestamp_format {
+        if destination != "-" { // not for the passthrough to the terminal
+            let format = format.clone();
+            filters.push(Box::new(LinePrefixer::new(Box::new(move || format!("{} ", format_timestamp(&format)).into_bytes()))));
+        }
+    }
+    if let Some(line_ending) = spec.line_ending {
+        if destination != "-" {
+            filters.push(Box::new(LineEndingConverter::new(line_ending)));
+        }
+    }
+    filters
+}
+
+fn apply_filters(filters: &mut [Box<dyn StreamFilter>], chunk: &[u8], finish: bool) -> Vec<u8> {
+    let mut data = chunk.to_vec();
+    for f in filters.iter_mut() {
+        data = f.filter(&data);
+        if finish {
+            data.extend(f.finish());
+        }
+    }
+    data
+}
+
+fn open_sink(fd: &str, temp_output: Option<&TempOutput>, passthrough: Stream, inherited_fd: Option<i32>, append_header: Option<&str>) -> Result<Box<dyn Write>> {
+    Ok(match fd {
+        "=" => Box::new(temp_output.unwrap().counting_writer()?),
+        "." => Box::new(io::sink()),
+        "-" => passthrough_writer(passthrough, inherited_fd)?,
+        _ => Box::new(open_output_file(fd, append_header)?),
+    })
+}
+
+/// Writes the output both to its file and through to the same stream of o-o (option --tee).
+struct TeeWriter {
+    file: Box<dyn Write>,
+    passthrough: Box<dyn Write>,
+}
+
+impl Write for TeeWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.passthrough.write_all(&buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.passthrough.flush()
+    }
+}
+
+/// The digest algorithm of `--stdout-hash`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HashAlgorithm {
+    Sha256,
+    Md5,
+}
+
+impl HashAlgorithm {
+    pub fn name(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Md5 => "md5",
+        }
+    }
+}
+
+/// A running digest of the bytes written to a sink.
+enum OutputHasher {
+    Sha256(sha2::Sha256),
+    Md5(md5::Md5),
+}
+
+impl OutputHasher {
+    fn new(algorithm: HashAlgorithm) -> OutputHasher {
+        use sha2::Digest;
+
+        match algorithm {
+            HashAlgorithm::Sha256 => OutputHasher::Sha256(sha2::Sha256::new()),
+            HashAlgorithm::Md5 => OutputHasher::Md5(md5::Md5::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        use sha2::Digest;
+
+        match self {
+            OutputHasher::Sha256(h) => h.update(data),
+            OutputHasher::Md5(h) => h.update(data),
+        }
+    }
+
+    fn finalize_hex(self) -> String {
+        use sha2::Digest;
+
+        let digest = match self {
+            OutputHasher::Sha256(h) => h.finalize().to_vec(),
+            OutputHasher::Md5(h) => h.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// A destination of the captured output, remembering the last byte written to it and counting the newlines.
+/// The hasher, if any, digests the bytes written.
+struct CaptureSink {
+    inner: Box<dyn Write>,
+    last: Option<u8>,
+    newlines: u64,
+    hasher: Option<OutputHasher>,
+}
+
+impl CaptureSink {
+    fn new(inner: Box<dyn Write>) -> CaptureSink {
+        CaptureSink { inner, last: None, newlines: 0, hasher: None }
+    }
+
+    /// The number of lines written. A last line without a newline is counted as a line, too.
+    fn lines(&self) -> u64 {
+        self.newlines + u64::from(self.last.is_some_and(|b| b != b'\n'))
+    }
+
+    /// Writes a newline unless nothing has been written or the output already ends with one.
+    fn ensure_trailing_newline(&mut self) -> io::Result<()> {
+        match self.last {
+            Some(b) if b != b'\n' => self.write_all(b"\n"),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Write for CaptureSink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        if n > 0 {
+            self.last = Some(buf[n - 1]);
+            self.newlines += buf[..n].iter().filter(|&&b| b == b'\n').count() as u64;
+            if let Some(hasher) = self.hasher.as_mut() {
+                hasher.update(&buf[..n]);
+            }
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The default limit of the bytes held by `--stdout-on-error` and `--stderr-on-error`.
+pub const DEFAULT_ON_ERROR_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Holds the last `capacity` bytes of a stream, discarding older ones.
+struct TailBuffer {
+    data: VecDeque<u8>,
+    capacity: usize,
+    discarded: usize,
+}
+
+impl TailBuffer {
+    fn new(capacity: usize) -> TailBuffer {
+        TailBuffer { data: VecDeque::new(), capacity, discarded: 0 }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.data.extend(bytes);
+        let overflow = self.data.len().saturating_sub(self.capacity);
+        self.data.drain(..overflow);
+        self.discarded += overflow;
+    }
+
+    fn write_to(&self, name: &str, sink: &mut dyn Write) -> io::Result<()> {
+        if self.discarded > 0 {
+            eprintln!("o-o: the first {} bytes of {} were discarded (buffer limit {} bytes)", self.discarded, name, self.capacity);
+        }
+        let (a, b) = self.data.as_slices();
+        sink.write_all(a)?;
+        sink.write_all(b)?;
+        sink.flush()
+    }
+}
+
+/// Runs the pipeline reading its stdout and stderr by o-o itself, and writes them to the
+/// destinations in the order of arrival. When stderr is `=`, the two streams are merged into
+/// the stdout's destination, chunk by chunk, rather than sharing a file descriptor.
+/// With `--stdout-on-error`/`--stderr-on-error`, a stream is held in memory and written out only when the pipeline fails.
+/// With `--abort-on-stderr`, the pipeline is killed once it writes to stderr. Returns the exit status, whether the
+/// pipeline wrote to stderr in that case, and the exit statuses of the stages.
+fn run_captured(pipeline: duct::Expression, spec: &PipelineSpec, fds: &[&str], temp_output: Option<&TempOutput>) -> Result<(ExitStatus, Option<i32>, Vec<Option<i32>>)> {
+    let append_header = spec.append_header.as_deref();
+    let tee = |fd: &str, sink: Box<dyn Write>, stream: Stream| -> Result<Box<dyn Write>> {
+        if !spec.tee || fd == "-" || fd == "." {
+            return Ok(sink);
+        }
+        let inherited_fd = spec.inherited_fds[if stream == Stream::Stdout { 1 } else { 2 }];
+        Ok(Box::new(TeeWriter { file: sink, passthrough: passthrough_writer(stream, inherited_fd)? }))
+    };
+    let mut stdout_sink = CaptureSink::new(tee(fds[1], open_sink(fds[1], temp_output, Stream::Stdout, spec.inherited_fds[1], append_header)?, Stream::Stdout)?);
+    stdout_sink.hasher = spec.stdout_hash.map(OutputHasher::new);
+    let mut stderr_sink = if fds[2] == "=" {
+        None
+    } else {
+        Some(CaptureSink::new(tee(fds[2], open_sink(fds[2], None, Stream::Stderr, spec.inherited_fds[2], append_header)?, Stream::Stderr)?))
+    };
+    let mut stdout_filters = stream_filters(spec, Stream::Stdout, fds[1]);
+    let mut stderr_filters = stream_filters(spec, Stream::Stderr, if fds[2] == "=" { fds[1] } else { fds[2] });
+    let mut stdout_buffer = spec.stdout_on_error.map(TailBuffer::new);
+    let mut stderr_buffer = spec.stderr_on_error.map(TailBuffer::new);
+
+    // Writes the data of a stream to its buffer if any, otherwise to its sink.
+    // The stderr merged into the stdout's destination (`=`) shares the stdout's buffer, unless it has its own.
+    let mut emit = |stream: Stream, data: &[u8]| -> io::Result<()> {
+        let to_stdout = stream == Stream::Stdout || stderr_sink.is_none();
+        match stream {
+            Stream::Stderr if stderr_buffer.is_some() => stderr_buffer.as_mut().unwrap().push(data),
+            _ if to_stdout && stdout_buffer.is_some() => stdout_buffer.as_mut().unwrap().push(data),
+            _ if to_stdout => return stdout_sink.write_all(data),
+            _ => return stderr_sink.as_mut().unwrap().write_all(data),
+        }
+        Ok(())
+    };
+
+    let run = start_captured(pipeline, spec.pty)?;
+    let mut stages = StageWatcher::new(&run.handle);
+
+    let mut write_result: io::Result<()> = Ok(());
+    let mut stderr_written = false;
+    let mut stdout_written = false;
+    let idle_deadline = || spec.idle_timeout.map(|timeout| Instant::now() + timeout);
+    let mut last_output_deadline = idle_deadline();
+    loop {
+        let deadline = match (spec.deadline, last_output_deadline) {
+            (Some(d), Some(i)) => Some(d.min(i)),
+            (d, i) => d.or(i),
+        };
+        let Some((stream, chunk)) = recv_chunk(&run.rx, deadline) else {
+            if last_output_deadline.is_some_and(|d| Instant::now() >= d) && spec.deadline.is_none_or(|d| Instant::now() < d) {
+                let _ = run.handle.kill(); // the readers are not joined, as a grandchild process may still hold the pipes
+                return Err(OOError::IdleTimeout { timeout: spec.idle_timeout.unwrap() }.into());
+            }
+            break;
+        };
+        last_output_deadline = idle_deadline();
+        let filters = match stream {
+            Stream::Stdout => &mut stdout_filters,
+            Stream::Stderr => &mut stderr_filters,
+        };
+        stdout_written |= stream == Stream::Stdout && !chunk.is_empty();
+        write_result = emit(stream, &apply_filters(filters, &chunk, false));
+        if write_result.is_err() {
+            let _ = run.handle.kill();
+            break;
+        }
+        if spec.abort_on_stderr && stream == Stream::Stderr && !chunk.is_empty() && !stderr_written {
+            // The chunk has been written to the stderr's destination; the rest already read is written, too
+            stderr_written = true;
+            eprintln!("o-o: aborted, as the command wrote to stderr");
+            let _ = run.handle.kill();
+        }
+    }
+    if write_result.is_ok() {
+        write_result = emit(Stream::Stdout, &apply_filters(&mut stdout_filters, &[], true))
+            .and_then(|_| emit(Stream::Stderr, &apply_filters(&mut stderr_filters, &[], true)));
+    }
+
+    // On the deadline, the readers are not joined, as a grandchild process may still hold the pipes
+    let status = match spec.deadline {
+        Some(deadline) => wait_until(&run.handle, &mut stages, deadline, spec.timeout_signal)?,
+        None => {
+            stages.observe(true);
+            run.handle.wait()?.status
+        }
+    };
+    let rejected_exit_code = if stderr_written {
+        Some(EXIT_CODE_STDERR_WRITTEN)
+    } else if spec.fail_on_empty_output && status.success() && !stdout_written {
+        eprintln!("o-o: failed, as the command wrote nothing to stdout");
+        Some(EXIT_CODE_EMPTY_OUTPUT)
+    } else {
+        None
+    };
+    if write_result.is_ok() && !status.success() {
+        if let Some(buffer) = &stdout_buffer {
+            write_result = buffer.write_to("stdout", &mut stdout_sink);
+        }
+        if let (Ok(()), Some(buffer)) = (&write_result, &stderr_buffer) {
+            write_result = buffer.write_to("stderr", stderr_sink.as_mut().unwrap_or(&mut stdout_sink));
+        }
+    }
+    if write_result.is_ok() && spec.ensure_trailing_newline {
+        if is_named_file(fds[1]) || fds[1] == "=" {
+            write_result = stdout_sink.ensure_trailing_newline();
+        }
+        if let (Ok(()), Some(sink)) = (&write_result, stderr_sink.as_mut()) {
+            if is_named_file(fds[2]) {
+                write_result = sink.ensure_trailing_newline();
+            }
+        }
+    }
+    if write_result.is_ok() {
+        write_result = stdout_sink.flush().and_then(|_| stderr_sink.as_mut().map_or(Ok(()), |s| s.flush()));
+    }
+    write_result?;
+    if spec.report_lines && (is_named_file(fds[1]) || fds[1] == "=") {
+        // The original fds, as the stdout file of --atomic-stdout is handled as `=` here
+        let file = if spec.fds[1] == "=" { &spec.fds[0] } else { split_append_flag(&spec.fds[1]).0 };
+        eprintln!("o-o: wrote {} lines to {}", stdout_sink.lines(), file);
+    }
+    if let (Some(algorithm), Some(hasher)) = (spec.stdout_hash, stdout_sink.hasher.take()) {
+        eprintln!("o-o: stdout {} = {}", algorithm.name(), hasher.finalize_hex());
+    }
+    for r in run.readers {
+        let _ = r.join();
+    }
+
+    Ok((status, rejected_exit_code, stages.exit_codes()))
+}
+
+/// Runs a pipeline, passing chunks of its stdout and stderr to the callbacks as they arrive.
+/// The stdin of the spec is honored, while its stdout and stderr are ignored since the two streams go to the callbacks.
+/// When a callback returns an error, the pipeline is killed and the error is returned.
+pub fn run_streaming<F, G>(spec: &PipelineSpec, mut on_stdout: F, mut on_stderr: G) -> Result<i32>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+    G: FnMut(&[u8]) -> Result<()>,
+{
+    let stdin = open_stdin(spec)?;
+
+    let mut pipeline = build_expression(spec)?;
+
+    if let Some(stdin) = stdin {
+        pipeline = stdin.redirect(pipeline);
+    } else if let Some(fd) = spec.inherited_fds[0] {
+        pipeline = pipeline.stdin_file(dup_inherited_fd(fd)?);
+    } else if spec.stdin_null {
+        pipeline = pipeline.stdin_null();
+    }
+
+    let run = start_captured(pipeline, spec.pty)?;
+
+    let mut callback_error = None;
+    for (stream, chunk) in run.rx.iter() {
+        let r = match stream {
+            Stream::Stdout => on_stdout(&chunk),
+            Stream::Stderr => on_stderr(&chunk),
+        };
+        if let Err(e) = r {
+            callback_error = Some(e);
+            let _ = run.handle.kill();
+            break;
+        }
+    }
+    drop(run.rx);
+
+    let output = run.handle.wait()?;
+    if let Some(e) = callback_error {
+        return Err(e); // the readers are not joined, as a grandchild process may still hold the pipes
+    }
+    for r in run.readers {
+        let _ = r.join();
+    }
+
+    Ok(exit_code_of(output.status))
+}
+
+/// Builds a `PipelineSpec` fluently, as a typed alternative to parsing o-o's command-line arguments.
+///
+/// ```no_run
+/// # use o_o::PipelineBuilder;
+/// let exit_code = PipelineBuilder::new()
+///     .stdin("in.txt")
+///     .stdout_overwrite()
+///     .command(["sort"])
+///     .pipe(["uniq"])
+///     .env("LC_ALL", "C")
+///     .run()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct PipelineBuilder {
+    spec: PipelineSpec,
+}
+
+impl Default for PipelineBuilder {
+    fn default() -> Self {
+        PipelineBuilder::new()
+    }
+}
+
+impl PipelineBuilder {
+    pub fn new() -> PipelineBuilder {
+        PipelineBuilder {
+            spec: PipelineSpec {
+                fds: vec!["-".to_string(), "-".to_string(), "-".to_string()],
+                ..Default::default()
+            },
+        }
+    }
+
+    fn fd(mut self, index: usize, value: String) -> PipelineBuilder {
+        self.spec.fds[index] = value;
+        self
+    }
+
+    pub fn stdin<S: Into<String>>(self, path: S) -> PipelineBuilder {
+        self.fd(0, path.into())
+    }
+
+    pub fn stdout<S: Into<String>>(self, path: S) -> PipelineBuilder {
+        self.fd(1, path.into())
+    }
+
+    pub fn stdout_append<S: Into<String>>(self, path: S) -> PipelineBuilder {
+        self.fd(1, format!("+{}", path.into()))
+    }
+
+    /// Overwrites the stdin file with the output, when the pipeline succeeds (`=` as stdout).
+    pub fn stdout_overwrite(self) -> PipelineBuilder {
+        self.fd(1, "=".to_string())
+    }
+
+    pub fn stdout_null(self) -> PipelineBuilder {
+        self.fd(1, ".".to_string())
+    }
+
+    pub fn stderr<S: Into<String>>(self, path: S) -> PipelineBuilder {
+        self.fd(2, path.into())
+    }
+
+    pub fn stderr_append<S: Into<String>>(self, path: S) -> PipelineBuilder {
+        self.fd(2, format!("+{}", path.into()))
+    }
+
+    /// Redirects stderr to the same destination as stdout (`=` as stderr).
+    pub fn stderr_to_stdout(self) -> PipelineBuilder {
+        self.fd(2, "=".to_string())
+    }
+
+    pub fn stderr_null(self) -> PipelineBuilder {
+        self.fd(2, ".".to_string())
+    }
+
+    /// Sets the first command of the pipeline, discarding the commands set so far.
+    pub fn command<I, S>(mut self, command_line: I) -> PipelineBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.spec.commands = vec![command_line.into_iter().map(Into::into).collect()];
+        self
+    }
+
+    /// Connects a command to the end of the pipeline.
+    pub fn pipe<I, S>(mut self, command_line: I) -> PipelineBuilder
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.spec.commands.push(command_line.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn env<K: Into<String>, V: Into<String>>(mut self, key: K, value: V) -> PipelineBuilder {
+        self.spec.envs.push((key.into(), Some(value.into())));
+        self
+    }
+
+    pub fn env_remove<K: Into<String>>(mut self, key: K) -> PipelineBuilder {
+        self.spec.envs.push((key.into(), None));
+        self
+    }
+
+    pub fn working_directory<S: Into<String>>(mut self, dir: S) -> PipelineBuilder {
+        self.spec.working_directory = Some(dir.into());
+        self
+    }
+
+    pub fn force_overwrite(mut self, force_overwrite: bool) -> PipelineBuilder {
+        self.spec.force_overwrite = force_overwrite;
+        self
+    }
+
+    /// Validates the redirections in the same way as the command-line arguments, and returns the spec.
+    pub fn build(self) -> Result<PipelineSpec> {
+        let mut spec = self.spec;
+        if spec.commands.is_empty() || spec.commands.iter().any(|c| c.is_empty()) {
+            return Err(OOError::CLIError { message: "no command line specified".to_string() }.into());
+        }
+
+        let fds: Vec<&str> = spec.fds.iter().map(|s| s.as_str()).collect();
+        do_validate_fds(&fds, spec.force_overwrite)?;
+        if spec.fds[0] == "-" && spec.fds[1] == "=" {
+            spec.fds[1] = "-".to_string();
+        }
+
+        Ok(spec)
+    }
+
+    pub fn run(self) -> Result<i32> {
+        run_pipeline(&self.build()?)
+    }
+}
+
+/// Options of `parse_pipelines_with_options`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParseOptions {
+    /// Skips empty command lines between separators (including leading and trailing ones), instead of an error.
+    pub allow_empty_segments: bool,
+    /// Comment marker. A token equal to it makes the rest of the command line, up to the next separator
+    /// (including pipes), ignored. A command line consisting only of a comment is skipped.
+    pub comment: Option<String>,
+    /// Treats the pipe and separator as regular expressions, which a token has to match as a whole, instead of exact strings.
+    pub token_regex: bool,
+}
+
+/// A pipe or separator of `parse_pipelines_with_options`. An empty one matches no token.
+enum BoundaryToken<'a> {
+    Exact(&'a str),
+    Regex(regex::Regex),
+}
+
+impl BoundaryToken<'_> {
+    fn new<'a>(pattern: &'a str, name: &str, token_regex: bool) -> Result<BoundaryToken<'a>> {
+        if !token_regex || pattern.is_empty() {
+            return Ok(BoundaryToken::Exact(pattern));
+        }
+        let regex = regex::Regex::new(&format!("^(?:{})$", pattern))
+            .map_err(|e| OOError::CLIError { message: format!("invalid regular expression of the {}: {}\n{}", name, pattern, e) })?;
+        Ok(BoundaryToken::Regex(regex))
+    }
+
+    fn matches(&self, token: &str) -> bool {
+        match self {
+            BoundaryToken::Exact(s) => !s.is_empty() && token == *s,
+            BoundaryToken::Regex(r) => r.is_match(token),
+        }
+    }
+}
+
+/// Splits a command line into sequential pipelines (by the separator token), each of which is
+/// a list of commands (by the pipe token). An empty token disables the corresponding splitting.
+/// An empty command, e.g., a separator or pipe at the beginning or end, or two in a row, is an error.
+pub fn parse_pipelines(tokens: &[&str], pipe: &str, sep: &str) -> Result<Vec<Vec<Vec<String>>>> {
+    parse_pipelines_with_options(tokens, pipe, sep, &ParseOptions::default())
+}
+
+/// Same as `parse_pipelines`, with options relaxing or extending the syntax.
+pub fn parse_pipelines_with_options(tokens: &[&str], pipe: &str, sep: &str, options: &ParseOptions) -> Result<Vec<Vec<Vec<String>>>> {
+    let err = |message: &str| {
+        Err(OOError::CLIError { message: message.to_string() }.into())
+    };
+    let is_empty_segment = |pl: &Vec<Vec<String>>| pl.len() == 1 && pl[0].is_empty();
+    let pipe = BoundaryToken::new(pipe, "pipe", options.token_regex)?;
+    let sep = BoundaryToken::new(sep, "separator", options.token_regex)?;
+
+    let mut pipelines: Vec<Vec<Vec<String>>> = vec![vec![vec![]]];
+    let mut last_boundary = "";
+    let mut in_comment = false;
+    for &token in tokens {
+        let is_sep = sep.matches(token);
+        if in_comment && !is_sep {
+            continue;
+        }
+        if options.comment.as_deref() == Some(token) {
+            in_comment = true;
+            continue;
+        }
+        if is_sep {
+            let commented_out = std::mem::take(&mut in_comment) && is_empty_segment(pipelines.last().unwrap());
+            if commented_out || (options.allow_empty_segments && is_empty_segment(pipelines.last().unwrap())) {
+                continue;
+            }
+            if pipelines.last().unwrap().last().unwrap().is_empty() {
+                return err("empty command line (unexpected separator)");
+            }
+            pipelines.push(vec![vec![]]);
+            last_boundary = "separator";
+        } else if pipe.matches(token) {
+            let pl = pipelines.last_mut().unwrap();
+            if pl.last().unwrap().is_empty() {
+                return err("empty command line (unexpected pipe)");
+            }
+            pl.push(vec![]);
+            last_boundary = "pipe";
+        } else {
+            pipelines.last_mut().unwrap().last_mut().unwrap().push(token.to_string());
+        }
+    }
+
+    let skipped = options.allow_empty_segments || in_comment;
+    if skipped && pipelines.len() > 1 && is_empty_segment(pipelines.last().unwrap()) {
+        pipelines.pop();
+    }
+    if pipelines.last().unwrap().last().unwrap().is_empty() {
+        return if last_boundary.is_empty() || (skipped && last_boundary == "separator" && is_empty_segment(pipelines.last().unwrap())) {
+            err("no command line specified")
+        } else {
+            err(&format!("empty command line (unexpected {})", last_boundary))
+        };
+    }
+
+    Ok(pipelines)
+}
@@ -1,48 +1,17 @@
-#[macro_use]
-extern crate anyhow;
-
 use std::env;
-use std::fs::{self, OpenOptions};
-use std::thread::yield_now;
-
-use anyhow::Result;
-use thiserror::Error;
-
-use duct::cmd;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
 use tempfile::{tempdir, TempDir};
 
-use ng_clp::{is_argument, next_index, parse, unwrap_argument};
-
 use o_o::*;
-
-fn split_append_flag(file_name: &str) -> (&str, bool) {
-    if let Some(stripped) = file_name.strip_prefix('+') {
-        (stripped, true)
-    } else {
-        (file_name, false)
-    }
-}
-
-fn unpack_shorthand_args(a: &str) -> Option<Vec<&'static str>> {
-    if a.len() != 3 {
-        return None;
-    }
-
-    let mut v: Vec<&'static str> = vec![];
-    for c in a.chars() {
-        if c == '-' {
-            v.push("-");
-        } else if c == '.' {
-            v.push(".");
-        } else if c == '=' {
-            v.push("=");
-        } else {
-            return None;
-        }
-    }
-
-    return Some(v);
-}
+use o_o::args::*;
 
 fn is_filename_like_char(c: char) -> bool {
     c.is_alphanumeric() || c == '_' || c == '-' || c == '.'
@@ -60,7 +29,7 @@ fn replace_tempdir_name(arg: &str, tempdir_placeholder: &str, temp_dir_str: &str
         let prev = if i > 0 { parts[i - 1] } else { "" };
         let next = if i + 1 < parts.len() { parts[i + 1] } else { "" };
         let prev_last_char = if prev.is_empty() { ' ' } else { prev.chars().last().unwrap() };
-        let next_first_char = if next.is_empty() { ' ' } else { next.chars().nth(0).unwrap() };
+        let next_first_char = if next.is_empty() { ' ' } else { next.chars().next().unwrap() };
         if !is_filename_like_char(prev_last_char) && next_first_char == '/' {
             replaced_parts.push(temp_dir_str.to_owned());
             replacement_occurs = true;
@@ -79,282 +48,141 @@ fn replace_tempdir_name(arg: &str, tempdir_placeholder: &str, temp_dir_str: &str
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 const NAME: &str = env!("CARGO_PKG_NAME");
 
-#[derive(Error, Debug)]
-pub enum OOError {
-    #[error("o-o: {}", .message)]
-    CLIError { message: String },
-}
-
 const USAGE: &str = "Run a sub-process and customize how it handles standard I/O.
 
 Usage:
   o-o [options] <stdin> <stdout> <stderr> [--] <commandline>...
+  o-o [options] --commands-file=PATH <stdin> <stdout> <stderr>
+  o-o [options] --convert=dos2unix|unix2dos <stdin> <stdout> <stderr>
   o-o --help
   o-o --version
+  o-o [options] --dump-config
 
 Options:
-  <stdin>       File served as the standard input. Use `-` for no redirection.
+  <stdin>       File served as the standard input. Use `-` for no redirection, and `=` for the same file as the standard output, which is replaced as with `=` for <stdout>.
   <stdout>      File served as the standard output. Use `-` for no redirection, `=` for the same file as the standard input, and `.` for /dev/null.
   <stderr>      File served as the standard error. Use `-` for no redirection, `=` for the same file as the standard output, and `.` for /dev/null.
                 Prefix with `+` to append to the file (akin to the `>>` redirection in shell).
+  --stdin=FILE, --stdout=FILE, --stderr=FILE
+                                    Give <stdin>, <stdout> or <stderr> by an option, in place of the argument, which can then be omitted. The same `-`, `=`, `.` and `+` are accepted.
   -e VAR=VALUE                      Set environment variables.
+  -e VAR                            Pass the variable VAR of the environment of o-o to the command line, e.g. to keep it with --clear-env.
+  --unset=VAR, -u VAR               Remove an environment variable (`-e VAR=` sets it to the empty string instead). When both are given for a variable, the later one wins.
+  --env-file FILE                   Set the environment variables read from FILE, lines of `KEY=VALUE` (a value may be quoted; `\\n` is a newline in double quotes), or a JSON object of strings when FILE ends with `.json`. -e and -u take precedence.
+  --clear-env                       Run the commands with an empty environment, except for the variables given with -e or --env-passthrough.
+  --env-passthrough=VAR             With --clear-env, keep the variable VAR of the parent environment. Can be repeated.
   --pipe=STR, -p STR                String for pipe to connect subprocesses (`|` in shell) [default: `I`].
   --separator=STR, -s STR           String for separator of command lines (`;` in shell) [default: `J`].
+  --and-separator=STR               String for running the command line after it only when the previous one succeeds (`&&` in shell). No default.
+  --or-separator=STR                String for running the command line after it only when the previous one fails (`||` in shell). No default. The two have the same precedence, higher than that of --separator. A command line not run leaves the last exit status as is. A failure followed by the or-separator does not stop the command lines, and is not counted in the exit status of o-o.
+  --token-regex                     Treat the --pipe and --separator strings as regular expressions, each matching a whole token, e.g. `-p 'I|\\|'`.
+  --allow-empty-segments            Skip empty command lines, e.g., of a separator at the beginning or end, or two in a row, instead of an error.
+  --comment[=STR]                   Treat a token STR as a comment marker: the rest of the command line up to the next separator, including pipes, is ignored [default: `#`].
+  --convert=dos2unix|unix2dos       Convert the line endings of <stdin> to LF or CRLF and write them to <stdout>, by o-o itself instead of a command line.
+  --commands-file=PATH              Read the command lines from PATH, one per line, instead of the command-line arguments. Blank lines and lines starting with `#` are skipped.
+  --args0-file=PATH                 Read the arguments of the command line from PATH, separated by NUL characters (as by `find -print0`), which are put before the ones given in the command line. No quoting or splitting is applied to them.
+  <(PROG ARGS...)                   A token of this form in a command feeds the output of PROG to the stdin of the command (quote it in shell). PROG reads the stdin the command would have read.
+  --temp-prefix=STR                 Prefix of the name of the temporary file of `=` (and of --atomic-stdout).
+  --temp-suffix=STR                 Suffix of the name of the temporary file, e.g. `.json` for a tool which inspects the extension.
   --tempdir-placeholder=STR, -t STR     Placeholder string for temporary directory [default: `T`].
-  --force-overwrite, -F             Overwrite the file even if subprocess fails (exit status != 0). Valid only when <stdout> is `=`.
+  --force-overwrite, -F             Overwrite the file even if subprocess fails (exit status != 0). Valid only when <stdout> is `=`, or with --atomic-stdout.
   --keep-going, -k                  Only effective when multiple command lines are chained with the separator. Even if one command line fails, subsequent command lines continue to be executed.
-  --working-directory=DIR, -d DIR   Working directory.
+  --keep-going-exit=POLICY          Exit status reported with --keep-going when command lines fail: `first`, `last`, or `max` (the largest) of the failures [default: `first`].
+  --max-parallel=N                  Run up to N command lines chained with the separator concurrently. Requires --keep-going. The command lines must not share files.
+  --spawn-limit=N                   Abort once running the next command line would spawn more than N subprocesses in total. Each stage of a pipeline counts as one. o-o then exits with 1.
+  --lock-file=PATH                  Hold an exclusive advisory lock on the file PATH (created if missing) during the whole run, waiting while another o-o holds it. Serializes the runs sharing a resource.
+  --lock-timeout=SECS               Give up (exiting with 1) when the lock of --lock-file is not acquired within SECS seconds.
+  --deadline=SECS                   Kill the running command line and skip the remaining ones once SECS seconds have passed since o-o started, exiting with 124.
+  --signal-on-timeout=SIG           Send the signal SIG (a name like `TERM` or `HUP`, or a number) to the command line when the deadline passes, killing it only if still running after 2 seconds.
+  --idle-timeout=SECS               Kill the command line when it writes no output (to stdout or stderr) for SECS seconds, e.g. when it hangs without exiting, and exit with 124. The timer restarts on each output.
+  --retries=N                       Run a failed command line again, up to N times.
+  --retry-delay=MS                  Wait MS milliseconds before each retry [default: 0].
+  --retry-jitter=MS                 Randomize the delay before each retry by up to ±MS milliseconds, so that processes retrying together get apart.
+  --report-json[=FILE]              After running, write a JSON array of `{index, command, exit_code, duration_ms, stage_exit_codes}` of the command lines to FILE [default: stderr]. `stage_exit_codes` are those of the commands connected by the pipe, `null` for one not observed (e.g., killed by o-o). A command line stopped by an error of o-o (e.g., --deadline) is included with the exit status of o-o and an `error` message.
+  --trace-file=PATH                 Append a line of the timestamp, exit status, redirections, and command line of each command line run to PATH.
+  --working-directory=DIR, -d DIR   Working directory. The environment variables PWD (as an absolute path) and OLDPWD are set accordingly, unless given by -e or -u.
+                                    A command line in a pipeline may start with `cd:DIR` to run in DIR (relative to the working directory).
+                                    A relative DIR of a sub-command o-o is resolved against the working directory of the parent.
+  --chdir-to-input                  Run the command line in the directory of the file <stdin>, as an absolute path, unless -d is given. The files of <stdout> and <stderr> are still relative to the current directory.
+  --stdin-fd=N, --stdout-fd=N, --stderr-fd=N
+                                    Attach the stream of the subprocess to the descriptor N inherited by o-o, e.g. a socket passed by systemd (Unix only). The stream has to be `-`.
+  --argv0=NAME                      Give NAME as argv[0] to the (first) command of the command line, e.g. for busybox-style multicall programs (Unix only).
+  --chroot=DIR                      Change the root directory of the subprocesses to DIR before running them (Unix only). Requires the root privilege. The working directory is resolved inside DIR.
+  --interleave[=prefix]             Read stdout and stderr by o-o itself and write them out in the order of arrival. With `=prefix`, lines are prefixed with `out: ` or `err: `.
+  --label[=OUT,ERR]                 Prefix each line of stdout with `OUT: ` and each line of stderr with `ERR: ` [default: `1,2`].
+  --prefix-timestamps[=FORMAT]      Prefix each line written to a file with a timestamp, in a strftime-like FORMAT [default: RFC 3339]. Not applied to `-`.
+  --append-header=STR               Write a header line STR before the output to a file appended to (`+file`). `%t` in STR is replaced with the current time.
+  --glob                            Expand <stdin> as a glob pattern. Multiple matching files are concatenated in sorted order.
+  --stdin-json=FILE                 Read a JSON array from FILE and give its elements to the command as stdin, one per line (NDJSON). <stdin> has to be `-`.
+  --stdin-tee=FILE                  Also write a copy of the bytes given to the command as stdin to FILE, e.g. to see what the command received.
+  --tee                             Also write the output to the files of <stdout> and <stderr> (including `=`) through to the stdout and stderr of o-o, as tee(1), e.g. `--tee - out.txt err.txt` to see both streams while saving them.
+  --stdin-null                      Connect the standard input of the subprocess to an empty stream, so that it never waits for terminal input. <stdin> has to be `-`.
+  --no-inherit-stdin                When <stdin> is `-`, give the subprocess an empty standard input instead of that of o-o, e.g. for running in background. Unlike --stdin-null, a file <stdin> can be given.
+  --null-io                         Run the command with an empty stdin and discard its stdout and stderr, when only the exit status matters. The fds have to be `-` (or `.` for <stdout>/<stderr>).
+  --strip-bom                       Skip a UTF-8 BOM at the beginning of <stdin>.
+  --dereference-stdin               Resolve the symbolic links of <stdin> before opening it, so that `=` writes back to the file linked to. By default, `=` replaces the link itself with a regular file.
+  --pty                             Run the subprocess with its standard output connected to a pseudo-terminal, as if it were interactive (Unix only).
+  --force-color                     Set `CLICOLOR_FORCE=1` and `FORCE_COLOR=1`, and remove `NO_COLOR`, so that the subprocess emits colors even into files.
+  --strip-ansi[=both]               Remove ANSI escape sequences (colors, cursor moves, etc.) from stdout. With `=both`, also from stderr.
+  --lf, --crlf                      Convert the line endings of the output written to files into LF or CRLF. Not applied to `-`.
+  --stderr-on-error[=MAX_BYTES]     Hold stderr in memory and write it out only when the subprocess fails. Only the last MAX_BYTES are kept [default: 1048576].
+  --stdout-on-error[=MAX_BYTES]     Same as --stderr-on-error, but for stdout. Can not be used with `=` as <stdout>.
+  --skip-if-fresh                   Skip running the command line (exiting with 0) when the file <stdout> is newer than the file <stdin>, as a build step of make.
+  --exit-code-file=PATH             Write the exit status of o-o (as decimal text) to PATH after the run, whether the command lines succeed or not, also when o-o stops with an error (e.g., --deadline).
+  --output-template=TEMPLATE        Write the stdout of each command line separated by `J` to its own file, named by TEMPLATE with `%n` replaced by the index of the command line (from 1) and `%p` by the process ID of o-o, e.g. `out-%n.txt`. Applies to the command lines whose <stdout> is `-`, except for those of sub-command o-o.
+  --touch-output                    Make sure the named <stdout> and <stderr> files exist after the run, created empty if the command wrote nothing (e.g., failing with --atomic-stdout).
+  --chmod=MODE                      After the run, set the permissions of the named <stdout> and <stderr> files (and of the file replaced by `=`) to MODE in octal, e.g. `644`, `0644` or `0o644`.
+  --umask=MODE                      Set the file mode creation mask (in octal, as --chmod) for the files created by o-o and the command lines.
+  --atomic-stdout                   Write a named <stdout> file via a temporary file, which replaces it only when the command succeeds (or with --force-overwrite), as `=` does.
+  --publish-as=PATH                 After the command succeeds, rename the file <stdout> to PATH, atomically replacing a file there, e.g. to publish `data` as `data.done` only once it is complete. On a failure, <stdout> is left as written and PATH is not touched.
+  --abort-on-stderr                 Kill the command once it writes anything to stderr (which is still written to <stderr>), and exit with 1 even if the command would exit with 0.
+  --fail-on-empty-output            Exit with 1 when the command exits with 0 but writes nothing to stdout, whatever <stdout> is. With `=`, the file <stdin> is then left as is.
+  --stdout-hash=ALGO                After the run, print `o-o: stdout ALGO = HEX` to stderr, the digest of the bytes written to <stdout>. ALGO is `sha256` or `md5`.
+  --progress                        While a command line runs, show a spinner with the elapsed time on stderr. Shown only when stderr is a terminal which the command line does not write to (e.g., with <stderr> redirected to a file).
+  --report-lines                    After the run, print `o-o: wrote N lines to FILE` to stderr for the <stdout> file (or `=`). A last line without a newline is counted, too.
+  --ensure-trailing-newline         Append a newline to the output written to a file (including `=`), when it is not empty and does not end with one.
+  --no-sync                         Do not flush the written files to the storage device after the run. Faster, but the output may be lost on a power failure or crash.
+  --explain                         Print what the command lines do, in plain English, without running them.
+  --require=CMD                     Check that the command CMD is found on PATH before running anything, e.g. a helper used by a script. Exits with 127 when not found. Can be given multiple times.
+  --capture=NAME:FILE               Run the command, and append `NAME=VALUE` to FILE for sourcing it in shell, where VALUE is the stdout of the command without the trailing newlines (quoted if needed). Nothing is written when the command fails. Can not be used with a pipe, multiple command lines, or a redirection of <stdout> or <stderr>.
+  --setup=CMD                       Run the command CMD (split into words as in shell) once before the command lines, with their environment variables and working directory. When it fails, nothing else is run (even with --keep-going), and o-o exits with its exit status.
+  --teardown=CMD                    Run the command CMD once after the command lines, whether they succeed or not, unless --setup fails. Its failure makes the exit status of o-o nonzero only when the command lines succeed.
+  --dry-run                         Print the command lines without running them, checking that the program of each is found on PATH. Exits with 127 when any is not found.
+  --dump-config                     Print the effective configuration, including the options given by OO_ARGS and the defaults, and exit.
   --version, -V                     Version information.
   --help, -h                        Shows this help message.
-";
-
-#[derive(Debug, PartialEq)]
-struct Args<'s> {
-    fds: Vec<&'s str>,
-    command_line: Vec<&'s str>,
-    force_overwrite: bool,
-    envs: Vec<(&'s str, &'s str)>,
-    working_directory: Option<&'s str>,
-    keep_going: bool,
-    debug_info: bool,
-    pipe_str: Option<&'s str>,
-    separator_str: Option<&'s str>,
-    tempdir_placeholder: Option<&'s str>,
-}
-
-impl Args<'_> {
-    fn parse<'s>(argv: &[&'s str]) -> anyhow::Result<Args<'s>> {
-        let mut args = Args {
-            fds: vec![],
-            command_line: vec![],
-            force_overwrite: false,
-            envs: vec![],
-            working_directory: None,
-            keep_going: false,
-            debug_info: false,
-            pipe_str: None,
-            separator_str: None,
-            tempdir_placeholder: None,
-        };
-
-        let argv = &argv[1..];
-        let mut argv_index = 0;
-        while args.fds.len() < 3 {
-            if args.fds.is_empty() {
-                if let Some(u) = unpack_shorthand_args(argv[argv_index]) {
-                    args.fds = u;
-                    argv_index += 1;
-                    break; // while
-                }
-            }
-            let pr = parse(argv, argv_index)?;
-            let eat = match pr.0 {
-                "-h" | "--help" => { // help
-                    print!("{}", USAGE);
-                    std::process::exit(0);
-                }
-                "-V" | "--version" => {
-                    println!("{} {}", NAME, VERSION);
-                    std::process::exit(0);
-                }
-                "-F" | "--force-overwrite" => {
-                    args.force_overwrite = true;
-                    1
-                }
-                "-k" | "--keep-going" => {
-                    args.keep_going = true;
-                    1
-                }
-                "--debug-info" => {
-                    args.debug_info = true;
-                    1
-                }
-                "-e" => {
-                    let value = unwrap_argument(pr)?;
-                    let p = value.find('=');
-                    if p.is_none() {
-                        return Err(OOError::CLIError { message: format!("option -e's argument should be `VAR=VALUE`: {}", pr.0) }.into());
-                    }
-                    let p = p.unwrap();
-                    args.envs.push((&value[..p], &value[p + 1..]));
-                    2
-                }
-                "-d" | "--working-directory" => {
-                    args.working_directory = Some(unwrap_argument(pr)?);
-                    2
-                }
-                "-p" | "--pipe"  => {
-                    args.pipe_str = Some(unwrap_argument(pr)?);
-                    2
-                }
-                "-s" | "--separator"  => {
-                    args.separator_str = Some(unwrap_argument(pr)?);
-                    2
-                }
-                "-t" | "--tempdir-placeholder" => {
-                    args.tempdir_placeholder = Some(unwrap_argument(pr)?);
-                    2
-                }
-                "--" => { // separator
-                    while args.fds.len() < 3 {
-                        args.fds.push("-");
-                    }
-                    break;
-                }
-                a if is_argument(a) => { // argument
-                    args.fds.push(a);
-                    1
-                }
-                _ => 0 // unknown flag/option 
-            };
-
-            argv_index = next_index(argv, argv_index, eat)?;
-            if argv_index >= argv.len() {
-                break;
-            }
-        }
-        if argv_index < argv.len() {
-            if argv[argv_index] == "--" { // in case a redundant "--" is given as the 4th argument
-                argv_index += 1;
-            }
-            args.command_line.extend_from_slice(&argv[argv_index..]);
-        }
-
-        if args.command_line.is_empty() {
-            return Err(OOError::CLIError { message: "no command line specified".to_string() }.into())
-        }
-
-        Ok(args)
-    }
-}
-
-fn do_validate_fds(fds: &[&str], force_overwrite: bool) -> std::result::Result<(), OOError> {
-    let err = |message: &str| {
-        Err(OOError::CLIError { message: message.to_string() })
-    };
-
-    if fds.len() < 3 {
-        return err("requires three arguments: stdin, stdout and stderr");
-    }
-
-    for fd in &fds[1..] {
-        if command_exists(fd) {
-            return Err(OOError::CLIError { message: format!("out/err looks a command: {}\n> (Use `--` to explicitly separate command from out/err)", fd)})
-        }
-    }
-
-    for i in 0..fds.len() {
-        if fds[i] == "+-" || fds[i] == "+=" {
-            return err("not possible to use `-` or `=` in combination with `+`");
-        }
-        if !(fds[i] == "-" || fds[i] == "=" || fds[i] == ".") {
-            for j in i + 1..fds.len() {
-                if split_append_flag(fds[j]).0 == split_append_flag(fds[i]).0 {
-                    return err("explicitly use `=` when dealing with the same file");
-                }
-            }
-        }
-    }
-
-    if force_overwrite {
-        if fds[0] == "-" {
-            return err("option --force-overwrite requires a real file name");
-        }
-        if fds[1] != "=" {
-            return err("option --force-overwrite is only valid when <stdout> is `=`");
-        }
-    }
 
-    if fds[0] == "=" || fds[0] == "." {
-        return err("can not specify either `=` or `.` as stdin");
-    }
-
-    Ok(())
-}
-
-fn run_pipeline(commands: &Vec<Vec<String>>, fds: &Vec<&str>, envs: &[(&str, &str)], working_directory: &Option<&str>,
-        force_overwrite: bool, tempdir_placeholder: &Option<&str>) -> Result<i32> {
-    let mut pipeline: Option<duct::Expression> = None;
-
-    for command in commands {
-        let mut duct_cmd = cmd(&command[0], &command[1..]);
-
-        if let Some(ref dir) = working_directory {
-            duct_cmd = duct_cmd.dir(dir);
-        }
-
-        for &(key, value) in envs {
-            duct_cmd = duct_cmd.env(key, value);
-        }
-
-        if let Some(existing_pipeline) = pipeline {
-            pipeline = Some(existing_pipeline.pipe(duct_cmd));
-        } else {
-            pipeline = Some(duct_cmd);
-        }
-    }
-
-    if let Some(mut final_pipeline) = pipeline {
-        let mut temp_file_path = None;
-
-        if fds[0] != "-" {
-            let file = OpenOptions::new().read(true).open(fds[0])?;
-            final_pipeline = final_pipeline.stdin_file(file);
-        }
-
-        match fds[1] {
-            "=" => {
-                let t = create_temp_file(tempdir_placeholder)?;
-                temp_file_path = Some(t.clone());
-                final_pipeline = final_pipeline.stdout_path(&t);
-            }
-            "." => {
-                final_pipeline = final_pipeline.stdout_null();
-            }
-            "-" => {
-            }
-            _ => {
-                let file = open_file_with_mode(fds[1])?;
-                final_pipeline = final_pipeline.stdout_file(file);
-            }
-        }
-
-        match fds[2] {
-            "=" => {
-                final_pipeline = final_pipeline.stderr_to_stdout();
-            }
-            "." => {
-                final_pipeline = final_pipeline.stderr_null();
-            }
-            "-" => {
-            }
-            _ => {
-                let file = open_file_with_mode(fds[2])?;
-                final_pipeline = final_pipeline.stderr_file(file);
-            }
-        }
-
-        let output = final_pipeline.unchecked().run()?;
-
-        yield_now(); // force occurs a context switch, hoping completion of file IOs
-
-        let status = output.status;
-        if status.success() || force_overwrite {
-            if let Some(temp_file) = temp_file_path {
-                fs::remove_file(fds[0])?;
-                if temp_file.exists() {
-                    fs::rename(&temp_file, fds[0])?;
-                } else {
-                    let file = OpenOptions::new().write(true).open(fds[0])?;
-                    file.set_len(0)?;
-                }
-            }
-        }
+Environment:
+  OO_ARGS       Options prepended to the command-line arguments, split as in shell. The options in the command line override them.
+";
 
-        Ok(status.code().unwrap())
-    } else {
-        Err(anyhow::anyhow!("No command to execute"))
-    }
+/// The environment variable giving the options prepended to the command-line arguments.
+const OO_ARGS_ENV: &str = "OO_ARGS";
+
+/// Prints the effective configuration (option --dump-config), i.e., the options after the fallbacks to OO_ARGS and the defaults.
+fn print_config(a: &Args, pipe_str: &str, separator_str: &str, td_placeholder: &str) {
+    println!("{} = {:?}", OO_ARGS_ENV, env::var(OO_ARGS_ENV).ok());
+    println!("pipe = {:?}", pipe_str);
+    println!("separator = {:?}", separator_str);
+    println!("and_separator = {:?}", a.and_separator_str);
+    println!("or_separator = {:?}", a.or_separator_str);
+    println!("tempdir_placeholder = {:?}", td_placeholder);
+    println!("force_color = {:?}", a.force_color);
+    println!("strip_ansi = {:?}", if a.strip_ansi_stderr { "both" } else if a.strip_ansi { "stdout" } else { "none" });
+    println!("force_overwrite = {:?}", a.force_overwrite);
+    println!("keep_going = {:?}", a.keep_going);
+    println!("keep_going_exit = {:?}", a.keep_going_exit);
+    println!("max_parallel = {:?}", a.max_parallel);
+    println!("spawn_limit = {:?}", a.spawn_limit);
+    println!("deadline = {:?}", a.deadline);
+    println!("idle_timeout = {:?}", a.idle_timeout);
+    println!("lock_file = {:?}", a.lock_file);
+    println!("lock_timeout = {:?}", a.lock_timeout);
+    println!("retries = {:?}", a.retries);
+    println!("retry_delay = {:?}", a.retry_delay.unwrap_or_default());
+    println!("retry_jitter = {:?}", a.retry_jitter.unwrap_or_default());
+    println!("envs = {:?}", a.envs);
+    println!("working_directory = {:?}", a.working_directory);
 }
 
 fn print_debug_info<S: AsRef<str>, T: AsRef<str>, U: AsRef<str>>(raw_args: &Args, pipelines : &[Vec<Vec<S>>], tempdir_replaced_arguments: &[(T, U)]) {
@@ -377,7 +205,7 @@ fn print_debug_info<S: AsRef<str>, T: AsRef<str>, U: AsRef<str>>(raw_args: &Args
             }
             for (j, a) in cml.iter().enumerate() {
                 if j > 0 {
-                    buf.push_str(" ");
+                    buf.push(' ');
                 }
                 buf.push_str(a.as_ref());
             }
@@ -389,32 +217,39 @@ fn print_debug_info<S: AsRef<str>, T: AsRef<str>, U: AsRef<str>>(raw_args: &Args
         println!();
         println!("tempdir-including arguments:");
         for tra in tempdir_replaced_arguments {
-            println!("{:?}", tra.0.as_ref());
+            if raw_args.debug_info0 {
+                print!("{}\0", tra.0.as_ref());
+            } else {
+                println!("{:?}", tra.0.as_ref());
+            }
         }
     }
 }
 
-fn reform_pipeline_for_2nd_or_later_oo_command_line<'s>(pl: &'s Vec<Vec<String>>, a: &'s Args) -> anyhow::Result<(Vec<Vec<String>>, Args<'s>)> {
-    let err = |message: &str| {
-        Err(OOError::CLIError { message: message.to_string() }.into())
-    };
-
-    let pl0: Vec<&str> = pl.get(0).unwrap().iter().map(|s| s.as_ref()).collect();
-    let mut sub_a = Args::parse(&pl0)?;
-    if sub_a.debug_info {
-        return err("invalid option used in sub-command: --debug-info");
-    }
-    if sub_a.pipe_str.is_some() {
-        return err("invalid option used in sub-command: --pipe");
-    }
-    if sub_a.separator_str.is_some() {
-        return err("invalid option used in sub-command: --separator");
+fn reform_pipeline_for_2nd_or_later_oo_command_line(pl: &[Vec<String>], a: &Args) -> anyhow::Result<PipelineSpec> {
+    let pl0: Vec<&str> = pl.first().unwrap().iter().map(|s| s.as_ref()).collect();
+    let mut sub_a = Args::parse_unchecked(&pl0)?;
+    if sub_a.help {
+        print!("{}", USAGE);
+        std::process::exit(0);
     }
-    if sub_a.tempdir_placeholder.is_some() {
-        return err("invalid option used in sub-command: --tempdir-placeholder=");
+    if sub_a.version {
+        println!("{} {}", NAME, VERSION);
+        std::process::exit(0);
     }
-
+    // The notation has been checked by `validate_command_lines`, and the files and commands are checked here
+    sub_a.glob = sub_a.glob || a.glob;
     do_validate_fds(&sub_a.fds, sub_a.force_overwrite)?;
+
+    // A relative working directory of the sub-command is resolved against the parent's one, not the current directory
+    let working_directory = match (a.working_directory, sub_a.working_directory) {
+        (Some(wd), Some(sd)) => Some(Path::new(wd).join(sd).to_string_lossy().into_owned()),
+        (wd, sd) => sd.or(wd).map(|d| d.to_string()),
+    };
+    if sub_a.chroot.is_none() {
+        sub_a.chroot = a.chroot;
+    }
+    validate_directories(working_directory.as_deref(), sub_a.chroot)?;
     if sub_a.fds[0] == "-" && sub_a.fds[1] == "=" {
         sub_a.fds[1] = "-";
     }
@@ -426,108 +261,649 @@ fn reform_pipeline_for_2nd_or_later_oo_command_line<'s>(pl: &'s Vec<Vec<String>>
     let mut sub_pl: Vec<Vec<String>> = vec![sub_pl0];
     sub_pl.extend_from_slice(&pl[1..]);
 
-    let mut envs: Vec<(&str, &str)> = vec![];
-    envs.extend_from_slice(&a.envs);
+    let mut envs = a.envs.clone();
     envs.extend_from_slice(&sub_a.envs);
     sub_a.envs = envs;
+    sub_a.clear_env = sub_a.clear_env || a.clear_env;
+    let mut env_passthrough = a.env_passthrough.clone();
+    env_passthrough.extend_from_slice(&sub_a.env_passthrough);
+    sub_a.env_passthrough = env_passthrough;
 
-    if sub_a.working_directory.is_none() {
-        sub_a.working_directory = a.working_directory;
-    }
     sub_a.force_overwrite = sub_a.force_overwrite || a.force_overwrite;
+    sub_a.tempdir_placeholder = a.tempdir_placeholder;
+
+    let mut spec = sub_a.pipeline_spec(&sub_pl);
+    spec.working_directory = working_directory;
+    // The sub-command's env files take precedence over the parent's -e options, but not over its own
+    spec.envs.splice(a.envs.len()..a.envs.len(), load_env_files(&sub_a.env_files)?);
+    Ok(spec)
+}
+
+/// Reads the env files (option --env-file) as variable assignments, in the order given.
+fn load_env_files(paths: &[&str]) -> anyhow::Result<Vec<(String, Option<String>)>> {
+    let mut envs = vec![];
+    for path in paths {
+        envs.extend(load_env_file(path)?.into_iter().map(|(k, v)| (k, Some(v))));
+    }
+    Ok(envs)
+}
+
+/// Validates the working directory and the new root of --chroot. With --chroot, the working directory is looked up inside the new root.
+fn validate_directories(working_directory: Option<&str>, chroot: Option<&str>) -> anyhow::Result<()> {
+    if let Some(root) = chroot {
+        if !Path::new(root).is_dir() {
+            return Err(OOError::CLIError { message: format!("option --chroot's argument should be a directory: {}", root) }.into());
+        }
+    }
+    if let Some(dir) = working_directory {
+        match chroot {
+            Some(root) => validate_working_directory(&Path::new(root).join(dir.trim_start_matches('/')).to_string_lossy())?,
+            None => validate_working_directory(dir)?,
+        }
+    }
+    Ok(())
+}
+
+/// The prefix of a process-substitution token, `<(PROG ARGS...)`.
+const PROCESS_SUBSTITUTION_PREFIX: &str = "<(";
+
+/// Parses a process-substitution token into the words of its command line, or returns `None` for another token.
+/// The parentheses in the token have to balance, with the last `)` closing the one of `<(`.
+fn parse_process_substitution(token: &str) -> Result<Option<Vec<String>>, OOError> {
+    if !token.starts_with(PROCESS_SUBSTITUTION_PREFIX) {
+        return Ok(None);
+    }
+    let unbalanced = || OOError::CLIError { message: format!("unbalanced parentheses in process substitution: {}", token) };
+
+    let mut depth = 0;
+    for (i, c) in token.char_indices().skip(1) {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 && i + 1 != token.len() {
+                    return Err(unbalanced());
+                }
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(unbalanced());
+    }
 
-    Ok((sub_pl, sub_a))
+    let inner = &token[PROCESS_SUBSTITUTION_PREFIX.len()..token.len() - 1];
+    let words = split_shell_words(inner, "process substitution")?;
+    if words.is_empty() {
+        return Err(OOError::CLIError { message: format!("process substitution has no command: {}", token) });
+    }
+    Ok(Some(words))
 }
 
-fn main() -> anyhow::Result<()> {
+/// Expands process-substitution tokens: a command with `<(PROG ARGS...)` among its arguments reads the output of
+/// PROG as its stdin, i.e., the token is removed and PROG is put before the command in the pipeline.
+/// PROG reads the stdin which the command would have read. Substitutions can be nested.
+fn expand_process_substitutions(commands: &[Vec<String>]) -> Result<Vec<Vec<String>>, OOError> {
+    let mut expanded = vec![];
+    for command in commands {
+        let mut producer = None;
+        let mut rest = vec![];
+        for token in command {
+            match parse_process_substitution(token)? {
+                Some(_) if producer.is_some() => {
+                    return Err(OOError::CLIError { message: format!("only one process substitution is allowed in a command: {}", token) });
+                }
+                Some(words) => producer = Some(words),
+                None => rest.push(token.clone()),
+            }
+        }
+        if let Some(words) = producer {
+            expanded.extend(expand_process_substitutions(&[words])?);
+        }
+        expanded.push(rest);
+    }
+    Ok(expanded)
+}
+
+/// Reads the command lines from a file (option --commands-file), one command line per line.
+/// Blank lines and lines starting with `#` are skipped.
+fn read_commands_file(path: &str, tokens: &SeparatorTokens, options: &ParseOptions) -> anyhow::Result<ConditionalPipelines> {
+    let content = fs::read_to_string(path).with_context(|| format!("Failed to read file: {}", path))?;
+    let (mut pipelines, mut conditions) = (vec![], vec![]);
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words = split_shell_words(line, &format!("{}:{}", path, i + 1))?;
+        let words: Vec<&str> = words.iter().map(AsRef::as_ref).collect();
+        let (p, c) = parse_conditional_pipelines(&words, tokens, options)?;
+        pipelines.extend(p);
+        conditions.extend(c);
+    }
+    if pipelines.is_empty() {
+        return Err(OOError::CLIError { message: format!("no command line in file: {}", path) }.into());
+    }
+    Ok((pipelines, conditions))
+}
+
+/// Reads the arguments of the command line from a file (option --args0-file), separated by NUL characters. A NUL at the
+/// end of the file terminates the last argument, rather than making an empty one.
+fn read_args0_file(path: &str) -> anyhow::Result<Vec<String>> {
+    let content = fs::read(path).with_context(|| format!("Failed to read file: {}", path))?;
+    let content = content.strip_suffix(b"\0").unwrap_or(&content);
+    if content.is_empty() {
+        return Err(OOError::CLIError { message: format!("no command line in file: {}", path) }.into());
+    }
+    content.split(|&b| b == 0)
+        .map(|a| String::from_utf8(a.to_vec()).map_err(|_| OOError::CLIError { message: format!("invalid UTF-8 in file: {}", path) }.into()))
+        .collect()
+}
+
+/// Exit code of o-o for errors in the command-line arguments, distinct from the exit codes of subprocesses that fail.
+const EXIT_CODE_CLI_ERROR: i32 = 2;
+
+/// The exit code of o-o for an error which stops the run.
+fn exit_code_of_error(e: &anyhow::Error) -> i32 {
+    match e.downcast_ref::<OOError>() {
+        Some(OOError::DeadlineExceeded | OOError::IdleTimeout { .. }) => EXIT_CODE_DEADLINE_EXCEEDED,
+        Some(OOError::ReplaceFile { .. }) => EXIT_CODE_REPLACE_FAILED,
+        Some(OOError::SpawnLimit { .. }) => EXIT_CODE_SPAWN_LIMIT,
+        Some(OOError::StdinFile { .. }) => 1,
+        Some(_) => EXIT_CODE_CLI_ERROR,
+        None if e.downcast_ref::<ng_clp::ParseError>().is_some() => EXIT_CODE_CLI_ERROR,
+        None => 1,
+    }
+}
+
+fn main() {
+    let mut exit_code_file = None;
+    let exit_code = match run_main(&mut exit_code_file) {
+        Ok(exit_code) => exit_code,
+        Err(e) => {
+            if e.downcast_ref::<OOError>().is_some() {
+                eprintln!("{}", e);
+            } else if e.downcast_ref::<ng_clp::ParseError>().is_some() {
+                eprintln!("o-o: {}", e);
+            } else {
+                eprintln!("o-o: {:#}", e);
+            }
+            exit_code_of_error(&e)
+        }
+    };
+    // Written here, so that the file records the exit code of any exit after the arguments are parsed, including errors
+    if let Some(path) = exit_code_file {
+        if let Err(e) = fs::write(&path, format!("{}\n", exit_code)) {
+            eprintln!("o-o: Failed to write file: {}: {}", path, e);
+            std::process::exit(1);
+        }
+    }
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+}
+
+/// Runs o-o, and returns its exit code. The path of option --exit-code-file is set as soon as the arguments are parsed.
+fn run_main(exit_code_file: &mut Option<String>) -> anyhow::Result<i32> {
+    let start = Instant::now();
+
     // Parse command-line arguments
-    let argv0: Vec<String> = env::args().collect();
-    let argv: Vec<&str> = argv0.iter().map(AsRef::as_ref).collect();
-    if argv.len() == 1 {
+    let mut argv0: Vec<String> = env::args().collect();
+    if argv0.len() == 1 {
         print!("{}", USAGE);
-        return Ok(());
+        return Ok(0);
+    }
+    if let Ok(oo_args) = env::var(OO_ARGS_ENV) {
+        // Options in OO_ARGS come first, so that the ones in the command line override them
+        let default_args = split_shell_words(&oo_args, OO_ARGS_ENV)?;
+        argv0.splice(1..1, default_args);
     }
+    let argv: Vec<&str> = argv0.iter().map(AsRef::as_ref).collect();
 
     let mut a = Args::parse(&argv)?;
+    if a.help {
+        print!("{}", USAGE);
+        return Ok(0);
+    }
+    if a.version {
+        println!("{} {}", NAME, VERSION);
+        return Ok(0);
+    }
+    *exit_code_file = a.exit_code_file.map(str::to_string);
+    let args0_words;
+    if let Some(path) = a.args0_file {
+        // The arguments of the file come first, where the command line would start
+        args0_words = read_args0_file(path)?;
+        a.command_line.splice(0..0, args0_words.iter().map(AsRef::as_ref));
+    }
 
     let td_placeholder = a.tempdir_placeholder.unwrap_or("T");
-    let pipe_str = a.pipe_str.unwrap_or("I");
-    let separator_str = a.separator_str.unwrap_or("J");
+    let separator_tokens = a.separator_tokens();
+    validate_separator_tokens(&separator_tokens, a.token_regex)?;
+
+    if a.dump_config {
+        print_config(&a, separator_tokens.pipe, separator_tokens.separator, td_placeholder);
+        return Ok(0);
+    }
+
+    // Held until o-o exits, so that the whole run is serialized with the other runs locking the same file
+    let _lock = a.lock_file.map(|path| lock_file(path, a.lock_timeout)).transpose()?;
 
     // Split sub-commands and replace temporary-directory path
-    let mut pipelines: Vec<Vec<Vec<String>>> = vec![vec![vec![]]];
+    let parse_options = a.parse_options();
+    let (mut pipelines, conditions) = if a.convert.is_some() {
+        (vec![vec![]], vec![RunCondition::Always]) // o-o itself works as the command
+    } else if let Some(path) = a.commands_file {
+        read_commands_file(path, &separator_tokens, &parse_options)?
+    } else {
+        parse_conditional_pipelines(&a.command_line, &separator_tokens, &parse_options)?
+    };
     let mut temp_dir: Option<TempDir> = None;
-    let mut tdrep_args: Vec<(&str, String)> = vec![];
-    for arg in a.command_line.iter() {
-        if !separator_str.is_empty() && *arg == separator_str {
-            if pipelines.last().unwrap().is_empty() {
-                return Err(anyhow!("o-o: empty command line (unexpected separator)"));
-            }
-            pipelines.push(vec![vec![]]);
-        } else if !pipe_str.is_empty() && *arg == pipe_str {
-            let pl = pipelines.last_mut().unwrap();
-            if pl.last().unwrap().is_empty() {
-                return Err(anyhow!("o-o: empty command line (unexpected pipe)"));
-            }
-            pl.push(vec![]);
-        } else {
-            // Replace temp-directory holder string to a real temp-directory path
-            let r = replace_tempdir_name(arg, td_placeholder, "dummy");
-            pipelines.last_mut().unwrap().last_mut().unwrap().push(
-                if r.is_some() {
-                    let td = temp_dir.get_or_insert_with(|| tempdir().unwrap());
-                    let td_path_str = td.path().to_str().unwrap();
-                    let r = replace_tempdir_name(arg, td_placeholder, td_path_str).unwrap();
-                    tdrep_args.push((arg, r.clone()));
-                    r
-                } else {
-                    arg.to_string()
-                }
-            );
+    let mut tdrep_args: Vec<(String, String)> = vec![];
+    for arg in pipelines.iter_mut().flatten().flatten() {
+        // Replace temp-directory holder string to a real temp-directory path
+        if replace_tempdir_name(arg, td_placeholder, "dummy").is_some() {
+            let td = temp_dir.get_or_insert_with(|| tempdir().unwrap());
+            let td_path_str = td.path().to_str().unwrap();
+            let r = replace_tempdir_name(arg, td_placeholder, td_path_str).unwrap();
+            tdrep_args.push((arg.clone(), r.clone()));
+            *arg = r;
         }
     }
 
     if a.debug_info {
         print_debug_info(&a, &pipelines, &tdrep_args);
-        return Ok(());
+        return Ok(0);
     }
 
-    // Validate command-line arguments
-    do_validate_fds(&a.fds, a.force_overwrite)?;
+    // Validate command-line arguments, by the notation as `validate` does, and then by the files and commands
+    validate_args(&a)?;
+    validate_command_lines(&a, &pipelines, &conditions)?;
+    do_validate_fds(&a.fds, a.force_overwrite && !a.atomic_stdout)?;
+    validate_directories(a.working_directory, a.chroot)?;
     if a.fds[0] == "-" && a.fds[1] == "=" {
         a.fds[1] = "-";
     }
 
-    // Exec 1st pipeline
-    let pl = pipelines.remove(0);
-    let mut exit_code = run_pipeline(&pl, &a.fds, &a.envs, &a.working_directory, 
-        a.force_overwrite, &a.tempdir_placeholder)?;
-    if ! a.keep_going && exit_code != 0 {
-        std::process::exit(exit_code);
+    // Make the specs of the pipelines
+    let mut specs = vec![a.pipeline_spec(&pipelines.remove(0))];
+    if let Some(template) = a.output_template {
+        if specs[0].fds[1] == "-" {
+            specs[0].fds[1] = expand_output_template(template, 1);
+        }
     }
-
-    // Exec 2nd or later pipeline
     let non_redirected_fds = vec!["-", "-", "-"];
     a.fds = non_redirected_fds; // The second and subsequent pipelines do not redirect unless you explicitly write the o-o command
-    for pl in pipelines.into_iter() {
-        let pl0: Vec<&str> = pl.get(0).unwrap().iter().map(|s| s.as_ref()).collect();
+    a.inherited_fds = [None; 3];
+    // as are the options applying to the stdin and stdout files of the first one
+    a.stdin_tee = None;
+    a.atomic_stdout = false;
+    a.publish_as = None;
+    for pl in pipelines.iter() {
+        let pl0: Vec<&str> = pl.first().unwrap().iter().map(|s| s.as_ref()).collect();
         let cmd_is_oo = !pl0.is_empty() && pl0[0] == "o-o";
-        exit_code = if cmd_is_oo {
-            let (sub_pl, sub_a) = reform_pipeline_for_2nd_or_later_oo_command_line(&pl, &a)?;
-            run_pipeline(&sub_pl, &sub_a.fds, &sub_a.envs, &sub_a.working_directory,
-                a.force_overwrite, &a.tempdir_placeholder)?
+        specs.push(if cmd_is_oo {
+            reform_pipeline_for_2nd_or_later_oo_command_line(pl, &a)?
         } else {
-            run_pipeline(&pl, &a.fds, &a.envs, &a.working_directory,
-                a.force_overwrite, &a.tempdir_placeholder)?
+            let mut spec = a.pipeline_spec(pl);
+            if let Some(template) = a.output_template {
+                spec.fds[1] = expand_output_template(template, specs.len() + 1);
+            }
+            spec
+        });
+    }
+
+    let file_envs = load_env_files(&a.env_files)?;
+    for spec in specs.iter_mut() {
+        spec.commands = expand_process_substitutions(&spec.commands)?;
+        // The env files are read before the -e and -u options, which take precedence over them
+        spec.envs.splice(0..0, file_envs.iter().cloned());
+    }
+
+    if let Some(deadline) = a.deadline {
+        for spec in specs.iter_mut() {
+            spec.deadline = Some(start + deadline);
+        }
+    }
+
+    if a.explain {
+        for (i, spec) in specs.iter().enumerate() {
+            if specs.len() > 1 {
+                print!("{}. ", i + 1);
+            }
+            println!("{}", explain_spec(spec));
+        }
+        return Ok(0);
+    }
+
+    let missing = missing_required_commands(&a.required_commands);
+    if !missing.is_empty() {
+        for command in missing {
+            eprintln!("o-o: required command not found: {}", command);
+        }
+        return Ok(EXIT_CODE_COMMAND_NOT_FOUND);
+    }
+
+    if a.dry_run {
+        return dry_run(&specs);
+    }
+
+    if let Some(mask) = a.umask {
+        set_umask(mask)?;
+    }
+
+    if let Some((name, file)) = a.capture {
+        return run_capture(&specs[0], name, file);
+    }
+
+    // Exec the pipelines
+    let context = RunContext {
+        trace: a.trace_file.map(TraceFile::open).transpose()?,
+        spawn_budget: a.spawn_limit.map(SpawnBudget::new),
+    };
+    // The setup command fails the whole run, even with --keep-going, and the teardown command runs once the setup succeeds
+    let setup_exit_code = match &a.setup_command {
+        Some(command) => run_hook_command("setup", command, &a, &file_envs)?,
+        None => 0,
+    };
+    // An error of o-o stops the run, but it is returned only after the report, which records the pipeline failed with it
+    let (results, error, teardown_exit_code) = if setup_exit_code != 0 {
+        (vec![], None, 0)
+    } else {
+        let (results, error) = match a.max_parallel {
+            Some(n) if n > 1 && specs.len() > 1 => run_pipelines_in_parallel(&specs, n, &context),
+            _ => run_pipelines_sequentially(&specs, &conditions, &a, &context),
         };
-        if ! a.keep_going && exit_code != 0 {
-            std::process::exit(exit_code);
+        let teardown = a.teardown_command.as_ref().map_or(Ok(0), |command| run_hook_command("teardown", command, &a, &file_envs));
+        let (teardown_exit_code, error) = match teardown {
+            Ok(code) => (code, error),
+            Err(e) => (0, error.or(Some(e))),
+        };
+        (results, error, teardown_exit_code)
+    };
+
+    let report = a.report_json.map(|report_file| write_report_json(report_file, &specs, &results)).transpose();
+    if let Some(e) = error {
+        return Err(e);
+    }
+    report?;
+
+    let exit_codes: Vec<i32> = results.iter().filter(|r| !r.recovered).map(|r| r.exit_code).collect();
+    let exit_code = match (setup_exit_code, a.keep_going_exit.exit_code(&exit_codes)) {
+        (0, 0) => teardown_exit_code,
+        (0, code) => code,
+        (code, _) => code,
+    };
+    Ok(exit_code)
+}
+
+/// Describes what running a pipeline does (option --explain), e.g.,
+/// "reads stdin from a.txt, writes stdout to b.txt (overwrite), discards stderr, runs: cat | wc -l".
+fn explain_spec(spec: &PipelineSpec) -> String {
+    let output = |fd: &str, name: &str| match fd {
+        "-" => format!("inherits {}", name),
+        "." => format!("discards {}", name),
+        "=" if name == "stdout" => "writes stdout back to the stdin file (overwrite)".to_string(),
+        "=" => "merges stderr into stdout".to_string(),
+        _ => match split_append_flag(fd) {
+            (path, true) => format!("writes {} to {} (append)", name, path),
+            (path, false) => format!("writes {} to {} (overwrite)", name, path),
+        },
+    };
+
+    let mut parts = vec![match spec.fds[0].as_str() {
+        "-" => "inherits stdin".to_string(),
+        "=" => format!("reads stdin from {}", spec.fds[1]),
+        path if spec.stdin_glob => format!("reads stdin from the files matching {}", path),
+        path => format!("reads stdin from {}", path),
+    }];
+    parts.push(if spec.fds[0] == "=" { output("=", "stdout") } else { output(&spec.fds[1], "stdout") });
+    parts.push(output(&spec.fds[2], "stderr"));
+    if let Some(dir) = &spec.working_directory {
+        parts.push(format!("in {}", dir));
+    }
+    parts.push(format!("runs: {}", format_pipeline(&spec.commands)));
+    parts.join(", ")
+}
+
+/// Exit code of o-o when --dry-run finds a command not found, the same as that of shell.
+const EXIT_CODE_COMMAND_NOT_FOUND: i32 = 127;
+
+/// Prints the pipelines without running them (option --dry-run), and checks that the program of every stage is found.
+/// Each missing program is reported on stderr, and the returned exit code is nonzero when there is any.
+fn dry_run(specs: &[PipelineSpec]) -> anyhow::Result<i32> {
+    let mut exit_code = 0;
+    for spec in specs {
+        println!("{}", format_pipeline(&spec.commands));
+        for command in &spec.commands {
+            let (_, command) = split_stage_directory(command)?;
+            if !command_exists(&command[0]) {
+                eprintln!("o-o: command not found: {}", command[0]);
+                exit_code = EXIT_CODE_COMMAND_NOT_FOUND;
+            }
         }
     }
+    Ok(exit_code)
+}
+
+/// Runs the command of option --capture, and appends `NAME=VALUE` of its stdout, without the trailing newlines, to the
+/// file, quoting the value as in shell if needed. Nothing is written when the command fails. Returns the exit status.
+fn run_capture(spec: &PipelineSpec, name: &str, file: &str) -> anyhow::Result<i32> {
+    let mut value = vec![];
+    let exit_code = run_streaming(
+        spec,
+        |chunk| {
+            value.extend_from_slice(chunk);
+            Ok(())
+        },
+        |chunk| Ok(std::io::stderr().write_all(chunk)?),
+    )?;
+    if exit_code == 0 {
+        let value = String::from_utf8_lossy(&value);
+        let mut f = fs::OpenOptions::new().create(true).append(true).open(file).with_context(|| format!("Failed to open file: {}", file))?;
+        writeln!(f, "{}={}", name, quote_argument(value.trim_end_matches(['\n', '\r'])))?;
+    }
+    Ok(exit_code)
+}
+
+/// Runs the command of option --setup or --teardown, with the environment variables and the working directory of the
+/// command lines, but without their redirections. A failure is reported on stderr. Returns the exit status.
+fn run_hook_command(name: &str, command: &[String], a: &Args, file_envs: &[(String, Option<String>)]) -> anyhow::Result<i32> {
+    let mut envs = file_envs.to_vec();
+    envs.extend(a.envs.iter().map(|(k, v)| (k.to_string(), v.as_ref().map(|v| v.to_string()))));
+    let spec = PipelineSpec {
+        commands: vec![command.to_vec()],
+        fds: vec!["-".to_string(); 3],
+        envs,
+        clear_env: a.clear_env,
+        env_passthrough: a.env_passthrough.iter().map(|s| s.to_string()).collect(),
+        working_directory: a.working_directory.map(|s| s.to_string()),
+        ..Default::default()
+    };
+    let exit_code = run_pipeline(&spec)?;
     if exit_code != 0 {
-        std::process::exit(exit_code);
+        eprintln!("o-o: {} command failed with exit status {}: {}", name, exit_code, format_pipeline(&spec.commands));
     }
+    Ok(exit_code)
+}
+
+/// Returns the commands required by option --require which are not found on PATH.
+fn missing_required_commands<'s>(commands: &[&'s str]) -> Vec<&'s str> {
+    commands.iter().copied().filter(|c| !command_exists(c)).collect()
+}
 
+/// A result of running a pipeline, with the error of o-o which stopped it, if any.
+type PipelineRun = (PipelineResult, Option<anyhow::Error>);
+
+/// The outcome of running a pipeline.
+struct PipelineResult {
+    /// The index of the pipeline in the command lines.
+    index: usize,
+    exit_code: i32,
+    stage_exit_codes: Vec<Option<i32>>,
+    duration: Duration,
+    /// Whether the failure is followed by a command line run after the or-separator, which leaves it out of the exit status of o-o.
+    recovered: bool,
+    /// The message of the error of o-o which stopped the pipeline (e.g., --deadline), if any.
+    error: Option<String>,
+}
+
+impl PipelineResult {
+    /// Runs the pipeline. An error of o-o which stops the run is returned along with the result recording it, whose
+    /// exit code is that o-o exits with for the error, and whose stages are all not observed.
+    fn run(index: usize, spec: &PipelineSpec, context: &RunContext) -> PipelineRun {
+        let start = Instant::now();
+        let outcome = match &context.spawn_budget {
+            Some(budget) => budget.reserve(spec.commands.len()).and_then(|()| run_pipeline_outcome(spec)),
+            None => run_pipeline_outcome(spec),
+        };
+        let (outcome, mut error) = match outcome {
+            Ok(outcome) => (outcome, None),
+            Err(e) => (RunOutcome { exit_code: exit_code_of_error(&e), stage_exit_codes: vec![None; spec.commands.len()] }, Some(e)),
+        };
+        let result = PipelineResult {
+            index,
+            exit_code: outcome.exit_code,
+            stage_exit_codes: outcome.stage_exit_codes,
+            duration: start.elapsed(),
+            recovered: false,
+            error: error.as_ref().map(|e| format!("{:#}", e).trim_start_matches("o-o: ").to_string()),
+        };
+        if let Some(trace) = &context.trace {
+            if let Err(e) = trace.record(spec, &result) {
+                error.get_or_insert(e);
+            }
+        }
+        (result, error)
+    }
+}
+
+/// The state shared by the runs of the pipelines.
+struct RunContext {
+    trace: Option<TraceFile>,
+    spawn_budget: Option<SpawnBudget>,
+}
+
+/// The number of subprocesses that may still be spawned (option --spawn-limit).
+/// Each stage of a pipeline counts as one subprocess.
+struct SpawnBudget {
+    limit: usize,
+    spawned: AtomicUsize,
+}
+
+impl SpawnBudget {
+    fn new(limit: usize) -> SpawnBudget {
+        SpawnBudget { limit, spawned: AtomicUsize::new(0) }
+    }
+
+    /// Reserves `count` subprocesses, or fails without reserving any when that would exceed the limit.
+    fn reserve(&self, count: usize) -> anyhow::Result<()> {
+        self.spawned
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |spawned| {
+                Some(spawned + count).filter(|&n| n <= self.limit)
+            })
+            .map_err(|spawned| OOError::SpawnLimit { spawned, limit: self.limit })?;
+        Ok(())
+    }
+}
+
+/// The file logging the executed command lines (option --trace-file).
+/// Each line is flushed as soon as written, so that a killed run still leaves a partial trace.
+struct TraceFile {
+    file: Mutex<fs::File>,
+}
+
+impl TraceFile {
+    fn open(path: &str) -> anyhow::Result<TraceFile> {
+        let file = fs::OpenOptions::new().create(true).append(true).open(path)
+            .with_context(|| format!("Failed to open file: {}", path))?;
+        Ok(TraceFile { file: Mutex::new(file) })
+    }
+
+    fn record(&self, spec: &PipelineSpec, result: &PipelineResult) -> anyhow::Result<()> {
+        let line = format!(
+            "{} exit={} stdin={} stdout={} stderr={} command={}\n",
+            format_timestamp(DEFAULT_TIMESTAMP_FORMAT),
+            result.exit_code,
+            quote_argument(&spec.fds[0]),
+            quote_argument(&spec.fds[1]),
+            quote_argument(&spec.fds[2]),
+            format_pipeline(&spec.commands),
+        );
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+}
+
+/// Runs the pipelines one after another as their conditions allow, and returns their results in the order run,
+/// along with the error of o-o of the pipeline stopped by one, which stops the run.
+fn run_pipelines_sequentially(specs: &[PipelineSpec], conditions: &[RunCondition], a: &Args, context: &RunContext) -> (Vec<PipelineResult>, Option<anyhow::Error>) {
+    // A command line not run for its condition leaves the last exit status as is. A failure stops the run (without
+    // --keep-going) unless the command line to run next is after the or-separator
+    let mut results: Vec<PipelineResult> = vec![];
+    let mut last_exit_code = 0;
+    for (index, (spec, condition)) in specs.iter().zip(conditions).enumerate() {
+        match condition {
+            RunCondition::OnSuccess if last_exit_code != 0 => continue,
+            RunCondition::OnFailure if last_exit_code == 0 => continue,
+            RunCondition::OnFailure => results.last_mut().unwrap().recovered = true,
+            RunCondition::Always if last_exit_code != 0 && !a.keep_going => break,
+            _ => {}
+        }
+        let (result, error) = PipelineResult::run(index, spec, context);
+        last_exit_code = result.exit_code;
+        results.push(result);
+        if error.is_some() {
+            return (results, error);
+        }
+    }
+    (results, None)
+}
+
+/// Runs the pipelines with up to `max_parallel` threads, and returns their results in the order of the pipelines,
+/// along with the error of o-o of the first pipeline stopped by one.
+fn run_pipelines_in_parallel(specs: &[PipelineSpec], max_parallel: usize, context: &RunContext) -> (Vec<PipelineResult>, Option<anyhow::Error>) {
+    let next = AtomicUsize::new(0);
+    let results: Vec<Mutex<Option<PipelineRun>>> = specs.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for _ in 0..max_parallel.min(specs.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= specs.len() {
+                    break;
+                }
+                *results[i].lock().unwrap() = Some(PipelineResult::run(i, &specs[i], context));
+            });
+        }
+    });
+    let (results, errors): (Vec<PipelineResult>, Vec<Option<anyhow::Error>>) = results.into_iter().map(|r| r.into_inner().unwrap().unwrap()).unzip();
+    (results, errors.into_iter().flatten().next())
+}
+
+/// Writes the results of the pipelines run (option --report-json) as a JSON array, to the file or to stderr.
+fn write_report_json(report_file: Option<&str>, specs: &[PipelineSpec], results: &[PipelineResult]) -> anyhow::Result<()> {
+    let entries: Vec<serde_json::Value> = results.iter().map(|result| {
+        let mut entry = serde_json::json!({
+            "index": result.index,
+            "command": format_pipeline(&specs[result.index].commands),
+            "exit_code": result.exit_code,
+            "duration_ms": result.duration.as_millis() as u64,
+            "stage_exit_codes": result.stage_exit_codes,
+        });
+        if let Some(error) = &result.error {
+            entry["error"] = error.as_str().into();
+        }
+        entry
+    }).collect();
+    let mut report = serde_json::to_string_pretty(&entries)?;
+    report.push('\n');
+
+    match report_file {
+        Some(path) => fs::write(path, report)?,
+        None => eprint!("{}", report),
+    }
     Ok(())
 }
 
@@ -550,12 +926,42 @@ mod fds_validate_test {
         assert!(do_validate_fds(&fds, false).is_err());
     }
 
+    #[test]
+    fn append_prefixed_stdin() {
+        let fds: Vec<&str> = vec!["+infile", "b", "c"];
+        match do_validate_fds(&fds, false) {
+            Err(OOError::CLIError { message }) => assert!(message.contains("append prefix `+` for stdin")),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
     #[test]
     fn invalid_usage_of_equal() {
-        let fds: Vec<&str> = vec!["=", "b", "c"];
+        let fds: Vec<&str> = vec!["=", "-", "c"];
+        assert!(do_validate_fds(&fds, false).is_err());
+
+        let fds: Vec<&str> = vec!["=", "=", "c"];
+        assert!(do_validate_fds(&fds, false).is_err());
+
+        let fds: Vec<&str> = vec!["=", "+b", "c"];
+        assert!(do_validate_fds(&fds, false).is_err());
+
+        let fds: Vec<&str> = vec![".", "b", "c"];
         assert!(do_validate_fds(&fds, false).is_err());
     }
 
+    #[test]
+    fn equal_as_stdin() {
+        let fds: Vec<&str> = vec!["=", "b", "c"];
+        assert!(do_validate_fds(&fds, false).is_ok());
+
+        let fds: Vec<&str> = vec!["=", "b", "="];
+        assert!(do_validate_fds(&fds, false).is_ok());
+
+        let fds: Vec<&str> = vec!["=", "b", "c"];
+        assert!(do_validate_fds(&fds, true).is_ok()); // --force-overwrite, as with `b = c`
+    }
+
     #[test]
     fn same_file_names() {
         let fds: Vec<&str> = vec!["a", "a", "b"];
@@ -607,6 +1013,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -626,6 +1033,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -645,6 +1053,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -664,6 +1073,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -683,9 +1093,70 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
+    #[test]
+    fn parse_two_char_shorthand_fds() {
+        let argv: Vec<&str> = vec!["exec", "-=", "cmd"];
+        let a = Args::parse(&argv).unwrap();
+
+        assert_eq!(a, Args { 
+            fds: vec!["-", "=", "-"],
+            command_line: vec!["cmd"],
+            force_overwrite: false,
+            keep_going: false,
+            envs: vec![],
+            working_directory: None,
+            debug_info: false,
+            pipe_str: None,
+            separator_str: None,
+            tempdir_placeholder: None,
+            ..Default::default()
+        });
+
+        let argv: Vec<&str> = vec!["exec", "-.", "cmd"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", ".", "-"]);
+        assert_eq!(a.command_line, vec!["cmd"]);
+
+        let argv: Vec<&str> = vec!["exec", "-x", "cmd"];
+        assert!(Args::parse(&argv).is_err());
+    }
+
+    #[test]
+    fn parse_double_dash_is_separator_not_shorthand() {
+        // `--` is always the separator; a following `--` belongs to the command line
+        let argv: Vec<&str> = vec!["exec", "--", "--", "cmd"];
+        let a = Args::parse(&argv).unwrap();
+
+        assert_eq!(a.fds, vec!["-", "-", "-"]);
+        assert_eq!(a.command_line, vec!["--", "cmd"]);
+    }
+
+    #[test]
+    fn parse_command_options_after_fds_verbatim() {
+        // the command's own flags, even the ones of o-o, are not parsed as o-o's options
+        let argv: Vec<&str> = vec!["exec", "-", "-", "-", "grep", "-e", "x", "-d", "--help", "-k"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", "-", "-"]);
+        assert_eq!(a.command_line, vec!["grep", "-e", "x", "-d", "--help", "-k"]);
+        assert_eq!(a.envs, vec![]);
+        assert!(!a.keep_going);
+
+        let argv: Vec<&str> = vec!["exec", "-k", "-.", "grep", "-e", "x"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", ".", "-"]);
+        assert_eq!(a.command_line, vec!["grep", "-e", "x"]);
+        assert!(a.keep_going);
+
+        let argv: Vec<&str> = vec!["exec", "in.txt", "--", "grep", "-u", "x"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["in.txt", "-", "-"]);
+        assert_eq!(a.command_line, vec!["grep", "-u", "x"]);
+    }
+
     #[test]
     fn parse_including_tempdir() {
         let argv: Vec<&str> = vec!["exec", "---", "cat", "T/hoge.txt"];
@@ -702,6 +1173,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -721,6 +1193,7 @@ mod main_tests {
             pipe_str: None,
             separator_str: None,
             tempdir_placeholder: Some("HOGE"),
+            ..Default::default()
         });
     }
 
@@ -740,6 +1213,7 @@ mod main_tests {
             pipe_str: Some("%%"),
             separator_str: None,
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
 
@@ -759,6 +1233,190 @@ mod main_tests {
             pipe_str: None,
             separator_str: Some("%%"),
             tempdir_placeholder: None,
+            ..Default::default()
         });
     }
+
+    #[test]
+    fn keep_going_exit_policies() {
+        let exit_codes = [0, 3, 0, 5, 4, 0];
+        assert_eq!(KeepGoingExit::First.exit_code(&exit_codes), 3);
+        assert_eq!(KeepGoingExit::Last.exit_code(&exit_codes), 4);
+        assert_eq!(KeepGoingExit::Max.exit_code(&exit_codes), 5);
+        assert_eq!(KeepGoingExit::Max.exit_code(&[0, 0]), 0);
+    }
+
+    #[test]
+    fn split_shell_words_quoting() {
+        assert_eq!(split_shell_words("  -t TMP\t--label  ", OO_ARGS_ENV).unwrap(), vec!["-t", "TMP", "--label"]);
+        assert_eq!(split_shell_words(r#"-e 'A=x y' -e "B=\"q\" \n" C\ D ''"#, OO_ARGS_ENV).unwrap(), vec!["-e", "A=x y", "-e", r#"B="q" \n"#, "C D", ""]);
+        assert!(split_shell_words("-e 'A=x", OO_ARGS_ENV).is_err());
+    }
+
+    #[test]
+    fn process_substitutions() {
+        let to_strings = |v: &[&[&str]]| -> Vec<Vec<String>> { v.iter().map(|c| c.iter().map(|s| s.to_string()).collect()).collect() };
+
+        let commands = to_strings(&[&["wc", "<(printf 'a (b)\\n')", "-l"]]);
+        assert_eq!(expand_process_substitutions(&commands).unwrap(), to_strings(&[&["printf", "a (b)\\n"], &["wc", "-l"]]));
+
+        let commands = to_strings(&[&["echo", "a"], &["sort", "<(cat '<(echo b)')"]]);
+        assert_eq!(expand_process_substitutions(&commands).unwrap(), to_strings(&[&["echo", "a"], &["echo", "b"], &["cat"], &["sort"]]));
+
+        for token in ["<(echo a", "<(echo a))", "<(echo a) b", "<()"] {
+            assert!(expand_process_substitutions(&to_strings(&[&["cat", token]])).is_err(), "{}", token);
+        }
+        assert!(expand_process_substitutions(&to_strings(&[&["cat", "<(echo a)", "<(echo b)"]])).is_err());
+    }
+
+    #[test]
+    fn parse_fd_options() {
+        let argv: Vec<&str> = vec!["exec", "--stdout=+out.txt", "--", "cat", "-n"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", "+out.txt", "-"]);
+        assert_eq!(a.command_line, vec!["cat", "-n"]);
+
+        let argv: Vec<&str> = vec!["exec", "--stderr", ".", "in.txt", "=", "sort"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["in.txt", "=", "."]);
+        assert_eq!(a.command_line, vec!["sort"]);
+
+        let argv: Vec<&str> = vec!["exec", "--stdin=a.txt", "--stdout=b.txt", "--stderr=c.txt", "cat"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["a.txt", "b.txt", "c.txt"]);
+        assert_eq!(a.command_line, vec!["cat"]);
+
+        // the positional of stdin conflicts with --stdin
+        let argv: Vec<&str> = vec!["exec", "--stdin=a.txt", "b.txt", "-", "cat"];
+        assert!(Args::parse(&argv).is_err());
+    }
+
+    #[test]
+    fn parse_options_after_fds() {
+        let argv: Vec<&str> = vec!["exec", "-", "-", "-", "-d", "/tmp", "-k", "cmd", "-d", "x"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", "-", "-"]);
+        assert_eq!(a.working_directory, Some("/tmp"));
+        assert!(a.keep_going);
+        assert_eq!(a.command_line, vec!["cmd", "-d", "x"]);
+
+        let argv: Vec<&str> = vec!["exec", "---", "-F", "cmd"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.fds, vec!["-", "-", "-"]);
+        assert!(a.force_overwrite);
+        assert_eq!(a.command_line, vec!["cmd"]);
+
+        // `--` ends the options, even when an o-o option follows it
+        let argv: Vec<&str> = vec!["exec", "-", "-", "-", "-k", "--", "-d", "x"];
+        let a = Args::parse(&argv).unwrap();
+        assert!(a.keep_going);
+        assert_eq!(a.working_directory, None);
+        assert_eq!(a.command_line, vec!["-d", "x"]);
+
+        // a flag o-o does not know starts the command line
+        let argv: Vec<&str> = vec!["exec", "-", "-", "-", "--no-such-option", "-d", "x"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.working_directory, None);
+        assert_eq!(a.command_line, vec!["--no-such-option", "-d", "x"]);
+    }
+
+    #[test]
+    fn parse_and_or_separators() {
+        use RunCondition::*;
+        let tokens = SeparatorTokens { pipe: "I", separator: "J", and_separator: Some("&&"), or_separator: Some("||") };
+        let words = vec!["a", "||", "b", "I", "c", "&&", "d", "J", "e"];
+        let (pipelines, conditions) = parse_conditional_pipelines(&words, &tokens, &ParseOptions::default()).unwrap();
+        assert_eq!(pipelines, vec![
+            vec![vec!["a".to_string()]],
+            vec![vec!["b".to_string()], vec!["c".to_string()]],
+            vec![vec!["d".to_string()]],
+            vec![vec!["e".to_string()]],
+        ]);
+        assert_eq!(conditions, vec![Always, OnFailure, OnSuccess, Always]);
+
+        // without the options, the tokens are arguments
+        let tokens = SeparatorTokens { pipe: "I", separator: "J", and_separator: None, or_separator: None };
+        let (pipelines, conditions) = parse_conditional_pipelines(&["echo", "&&"], &tokens, &ParseOptions::default()).unwrap();
+        assert_eq!(pipelines, vec![vec![vec!["echo".to_string(), "&&".to_string()]]]);
+        assert_eq!(conditions, vec![Always]);
+
+        let tokens = SeparatorTokens { pipe: "I", separator: "J", and_separator: Some("&&"), or_separator: Some("||") };
+        assert!(parse_conditional_pipelines(&["a", "&&"], &tokens, &ParseOptions::default()).is_err());
+        assert!(parse_conditional_pipelines(&["a", "||", "&&", "b"], &tokens, &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn parse_env_without_value() {
+        let home = std::env::var("HOME").unwrap();
+        let argv: Vec<&str> = vec!["exec", "-e", "HOME", "-e", "A=1", "-", "-", "-", "printenv"];
+        let a = Args::parse(&argv).unwrap();
+        assert_eq!(a.envs, vec![("HOME", Some(home.into())), ("A", Some("1".into()))]);
+
+        let argv: Vec<&str> = vec!["exec", "-e", "", "-", "-", "-", "printenv"];
+        assert!(Args::parse(&argv).is_err());
+    }
+
+    #[test]
+    fn validate_split_tokens() {
+        assert!(validate_split_token("--pipe", "I", false).is_ok());
+        assert!(validate_split_token("--separator", "%%", false).is_ok());
+        assert!(validate_split_token("--pipe", "", false).is_err());
+        assert!(validate_split_token("--pipe", "", true).is_err());
+        assert!(validate_split_token("--separator", " ", false).is_err());
+        assert!(validate_split_token("--separator", "a b", false).is_err());
+        assert!(validate_split_token("--pipe", "\t", false).is_err());
+        assert!(validate_split_token("--pipe", "a b", true).is_ok()); // a regex may match an argument with a space
+    }
+
+    #[test]
+    fn parse_option_argument_forms() {
+        // A value is taken attached to a short option, after `=` of a long option, or as the next argument
+        for argv in [
+            vec!["exec", "-pP", "-sS", "-tX", "-eA=1", "-", "-", "-", "cat"],
+            vec!["exec", "-p", "P", "-s", "S", "-t", "X", "-e", "A=1", "-", "-", "-", "cat"],
+            vec!["exec", "--pipe=P", "--separator=S", "--tempdir-placeholder=X", "-", "-", "-", "-e", "A=1", "cat"],
+            vec!["exec", "--pipe", "P", "--separator", "S", "--tempdir-placeholder", "X", "-e", "A=1", "-", "-", "-", "cat"],
+        ] {
+            let a = Args::parse(&argv).unwrap();
+            assert_eq!((a.pipe_str, a.separator_str, a.tempdir_placeholder), (Some("P"), Some("S"), Some("X")), "{:?}", argv);
+            assert_eq!(a.envs, vec![("A", Some("1".into()))], "{:?}", argv);
+            assert_eq!(a.fds, vec!["-", "-", "-"], "{:?}", argv);
+            assert_eq!(a.command_line, vec!["cat"], "{:?}", argv);
+        }
+
+        // A flag takes no value, and an option requires one
+        assert!(Args::parse(&["exec", "--keep-going=yes", "-", "-", "-", "cat"]).is_err());
+        assert!(Args::parse(&["exec", "-Fx", "-", "-", "-", "cat"]).is_err());
+        assert!(Args::parse(&["exec", "-", "-", "-", "cat", "-d"]).is_ok()); // an argument of the command
+        assert!(Args::parse(&["exec", "-d"]).is_err());
+        assert!(Args::parse(&["exec", "--pipe", "--", "-", "-", "-", "cat"]).is_err());
+    }
+
+    #[test]
+    fn parse_bundled_short_flags() {
+        for argv in [vec!["exec", "-Fk", "in.txt", "=", "-", "sort"], vec!["exec", "-kF", "in.txt", "=", "-", "sort"]] {
+            let a = Args::parse(&argv).unwrap();
+            assert!(a.force_overwrite && a.keep_going, "{:?}", argv);
+            assert_eq!(a.fds, vec!["in.txt", "=", "-"]);
+            assert_eq!(a.command_line, vec!["sort"]);
+        }
+
+        // ending in an option taking a value, given separately or attached
+        for argv in [vec!["exec", "-kd", "/tmp", "-", "-", "-", "ls"], vec!["exec", "-kd/tmp", "-", "-", "-", "ls"]] {
+            let a = Args::parse(&argv).unwrap();
+            assert!(a.keep_going, "{:?}", argv);
+            assert_eq!(a.working_directory, Some("/tmp"));
+            assert_eq!(a.command_line, vec!["ls"]);
+        }
+
+        // not a bundle: an option with its value attached, and a letter of no short option
+        let a = Args::parse(&["exec", "-pF", "-", "-", "-", "cat"]).unwrap();
+        assert_eq!(a.pipe_str, Some("F"));
+        assert!(!a.force_overwrite);
+        assert!(Args::parse(&["exec", "-kx", "-", "-", "-", "cat"]).is_err());
+
+        // the command line is left as is
+        let a = Args::parse(&["exec", "-", "-", "-", "ls", "-Fk"]).unwrap();
+        assert_eq!(a.command_line, vec!["ls", "-Fk"]);
+    }
 }
@@ -1,6 +1,5 @@
 #[cfg(test)]
 #[allow(non_snake_case)]
-
 mod test {
     use std::fs;
     use std::fs::File;
@@ -23,7 +22,7 @@ mod test {
         Ok(()) // 正常終了時にOk(())を返す
     }
 
-    fn SU<'a>(p: &'a Path) -> &'a str {
+    fn SU(p: &Path) -> &str {
         p.to_str().unwrap()
     }
 
@@ -47,7 +46,7 @@ mod test {
 
         let temp_file = temp_dir.path().join("ls-output.txt");
         let output = Command::new("./target/debug/o-o")
-            .args(["-d", SU(&temp_dir.path()), "-", SU(&temp_file), "-", "ls"])
+            .args(["-d", SU(temp_dir.path()), "-", SU(&temp_file), "-", "ls"])
             .output()?;
 
         assert_eq!(output.status.code().unwrap(), 0);
@@ -68,7 +67,7 @@ mod test {
         let status = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 "-",
@@ -95,7 +94,7 @@ mod test {
         let output = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "-",
                 "-",
@@ -127,7 +126,7 @@ mod test {
         let status = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 SU(&out_file),
                 SU(&err_file),
@@ -162,7 +161,7 @@ mod test {
         let output = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 "=",
@@ -191,7 +190,7 @@ mod test {
         let status1 = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 &append_out_file,
                 "-",
@@ -204,7 +203,7 @@ mod test {
         let status2 = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 &append_out_file,
                 "-",
@@ -232,7 +231,7 @@ mod test {
         write_and_wait(SU(&file_a), "file a.\n")?;
 
         let status = Command::new("./target/debug/o-o")
-            .args(["-d", SU(&temp_dir.path()), SU(&file_a), "=", "-", "wc"])
+            .args(["-d", SU(temp_dir.path()), SU(&file_a), "=", "-", "wc"])
             .status()?;
         assert!(status.code().unwrap() == 0);
 
@@ -255,7 +254,7 @@ mod test {
         let output = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-p",
                 "P",
                 SU(&file_a),
@@ -297,7 +296,7 @@ mod test {
         let status: std::process::ExitStatus = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "=",
                 "-",
@@ -309,7 +308,7 @@ mod test {
 
         let file_a_contents = fs::read_to_string(SU(&file_a))?;
         assert!(file_a_contents.find("original contents").is_some());
-        assert!(!file_a_contents.find("echo and fail!").is_some());
+        assert!(file_a_contents.find("echo and fail!").is_none());
 
         temp_dir.close()?;
         Ok(())
@@ -335,7 +334,7 @@ mod test {
             .args([
                 "-F",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "=",
                 "-",
@@ -346,7 +345,7 @@ mod test {
         assert!(status.code().unwrap() == 12);
 
         let file_a_contents = fs::read_to_string(SU(&file_a))?;
-        assert!(!file_a_contents.find("original contents").is_some());
+        assert!(file_a_contents.find("original contents").is_none());
         assert!(file_a_contents.find("echo and fail!").is_some());
 
         temp_dir.close()?;
@@ -365,7 +364,7 @@ mod test {
         let output = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-e",
                 "V=some",
                 "-",
@@ -390,13 +389,13 @@ mod test {
         let temp_dir = tempdir()?;
 
         let output = Command::new("./target/debug/o-o")
-            .args(["-d", SU(&temp_dir.path()), "-", ".", "-", "echo", "hello"])
+            .args(["-d", SU(temp_dir.path()), "-", ".", "-", "echo", "hello"])
             .output()?;
 
         assert!(output.status.code().unwrap() == 0);
 
         let output_contents = String::from_utf8(output.stdout).unwrap();
-        assert!(!output_contents.find("hello").is_some());
+        assert!(output_contents.find("hello").is_none());
 
         temp_dir.close()?;
         Ok(())
@@ -417,7 +416,7 @@ mod test {
         let output = Command::new("./target/debug/o-o")
             .args([
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 ".",
@@ -431,4 +430,2023 @@ mod test {
         temp_dir.close()?;
         Ok(())
     }
+
+    #[test]
+    fn environment_variable_empty_vs_unset() -> Result<(), io::Error> {
+        const SCRIPT: &str = "a_script.sh";
+
+        let temp_dir = tempdir()?;
+
+        let script = temp_dir.path().join(SCRIPT);
+        write_and_wait(SU(&script), "if [ -z \"${V+x}\" ]; then echo unset; else echo \"set:[$V]\"; fi\n")?;
+
+        let run = |opts: &[&str]| -> Result<String, io::Error> {
+            let mut args: Vec<&str> = vec!["-d", SU(temp_dir.path())];
+            args.extend_from_slice(opts);
+            args.extend_from_slice(&["-", "-", "-", "bash", SU(&script)]);
+            let output = Command::new("./target/debug/o-o")
+                .env("V", "present")
+                .args(&args)
+                .output()?;
+            assert!(output.status.code().unwrap() == 0);
+            Ok(String::from_utf8(output.stdout).unwrap())
+        };
+
+        assert_eq!(run(&[])?.trim(), "set:[present]");
+        assert_eq!(run(&["-e", "V="])?.trim(), "set:[]");
+        assert_eq!(run(&["-u", "V"])?.trim(), "unset");
+        assert_eq!(run(&["-e", "V=", "-u", "V"])?.trim(), "unset");
+        assert_eq!(run(&["-u", "V", "-e", "V="])?.trim(), "set:[]");
+
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn interleave_preserves_arrival_order() -> Result<(), io::Error> {
+        const SCRIPT: &str = "a_script.sh";
+
+        let temp_dir = tempdir()?;
+
+        let script = temp_dir.path().join(SCRIPT);
+        write_and_wait(SU(&script), "for i in 1 2 3; do echo out$i; sleep 0.05; echo err$i >&2; sleep 0.05; done\n")?;
+
+        let out_file = temp_dir.path().join("out.txt");
+        let status = Command::new("./target/debug/o-o")
+            .args(["--interleave", "-", SU(&out_file), "=", "bash", SU(&script)])
+            .status()?;
+        assert!(status.code().unwrap() == 0);
+
+        let out_file_contents = fs::read_to_string(SU(&out_file))?;
+        assert_eq!(out_file_contents, "out1\nerr1\nout2\nerr2\nout3\nerr3\n");
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--interleave=prefix", "-", SU(&out_file), "=", "bash", SU(&script)])
+            .status()?;
+        assert!(status.code().unwrap() == 0);
+
+        let out_file_contents = fs::read_to_string(SU(&out_file))?;
+        assert_eq!(out_file_contents, "out: out1\nerr: err1\nout: out2\nerr: err2\nout: out3\nerr: err3\n");
+
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_timestamps() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+
+        let out_file = temp_dir.path().join("out.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--prefix-timestamps", "-", SU(&out_file), "-", "printf", "1st line\\n2nd line\\n3rd line"])
+            .output()?;
+        assert!(output.status.code().unwrap() == 0);
+        assert!(output.stdout.is_empty());
+
+        let out_file_contents = fs::read_to_string(SU(&out_file))?;
+        let lines: Vec<&str> = out_file_contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        for (line, text) in lines.iter().zip(["1st line", "2nd line", "3rd line"]) {
+            let (timestamp, rest) = line.split_once(' ').unwrap();
+            assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok(), "{}", line);
+            assert_eq!(rest, text);
+        }
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--prefix-timestamps=%Y", "-", SU(&out_file), "-", "echo", "hello"])
+            .output()?;
+        assert!(output.status.code().unwrap() == 0);
+
+        let out_file_contents = fs::read_to_string(SU(&out_file))?;
+        let (year, rest) = out_file_contents.split_once(' ').unwrap();
+        assert!(year.parse::<u32>().is_ok());
+        assert_eq!(rest, "hello\n");
+
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn prefix_timestamps_not_applied_to_passthrough() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--prefix-timestamps", "-", "-", "-", "echo", "hello"])
+            .output()?;
+        assert!(output.status.code().unwrap() == 0);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--prefix-timestamps=%Q", "-", "-", "-", "echo", "hello"])
+            .output()?;
+        assert!(output.status.code().unwrap() != 0);
+        Ok(())
+    }
+
+    #[test]
+    fn label_merged_streams() -> Result<(), io::Error> {
+        const SCRIPT: &str = "a_script.sh";
+
+        let temp_dir = tempdir()?;
+
+        let script = temp_dir.path().join(SCRIPT);
+        write_and_wait(SU(&script), "echo out1; echo err1 >&2; echo out2; echo err2 >&2\n")?;
+
+        let out_file = temp_dir.path().join("out.txt");
+        let status = Command::new("./target/debug/o-o")
+            .args(["--label", "-", SU(&out_file), "=", "bash", SU(&script)])
+            .status()?;
+        assert!(status.code().unwrap() == 0);
+
+        let out_file_contents = fs::read_to_string(SU(&out_file))?;
+        let lines: Vec<&str> = out_file_contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        for line in lines {
+            let (label, text) = line.split_once(": ").unwrap();
+            assert_eq!(label, if text.starts_with("out") { "1" } else { "2" }, "{}", line);
+        }
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--label=O,E", "-", "-", "=", "bash", SU(&script)])
+            .output()?;
+        assert!(output.status.code().unwrap() == 0);
+
+        let mut lines: Vec<String> = String::from_utf8(output.stdout).unwrap().lines().map(|l| l.to_string()).collect();
+        lines.sort();
+        assert_eq!(lines, vec!["E: err1", "E: err2", "O: out1", "O: out2"]);
+
+        temp_dir.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn exit_code_of_cli_error() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with("o-o: "));
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--no-such-option", "-", "-", "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "=", "false"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn debug_info0_nul_separated_arguments() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--debug-info0", "-", "-", "-", "cat", "T/a\nb.txt", "T/c.txt"])
+            .output()?;
+        assert!(output.status.code().unwrap() == 0);
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let (_, args) = stdout.split_once("tempdir-including arguments:\n").unwrap();
+        let args: Vec<&str> = args.split_terminator('\0').collect();
+        assert_eq!(args, vec!["T/a\nb.txt", "T/c.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_stdin_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let missing = temp_dir.path().join("no-such-file.txt");
+        let output_file = temp_dir.path().join("output.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&missing), SU(&output_file), "-", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(stderr.trim_end(), format!("o-o: cannot read stdin file: {} (No such file or directory)", SU(&missing)));
+        assert!(!output_file.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn stage_working_directory() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let dir_a = temp_dir.path().join("a");
+        let dir_b = temp_dir.path().join("b");
+        fs::create_dir(&dir_a)?;
+        fs::create_dir(&dir_b)?;
+        write_and_wait(SU(&dir_a.join("input.txt")), "from a\n")?;
+
+        let output_file = temp_dir.path().join("output.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["-d", SU(temp_dir.path()), "-", SU(&output_file), "-", "cd:a", "cat", "input.txt", "I", "cd:b", "sh", "-c", "cat; pwd"])
+            .output()?;
+        assert!(output.status.success());
+
+        let content = fs::read_to_string(&output_file)?;
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines[0], "from a");
+        assert_eq!(fs::canonicalize(lines[1])?, fs::canonicalize(&dir_b)?);
+        Ok(())
+    }
+
+    #[test]
+    fn stage_working_directory_before_sub_oo() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "true", "J", "cd:/", "o-o", "-", "-", "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn glob_stdin() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        write_and_wait(SU(&temp_dir.path().join("b.txt")), "b\n")?;
+        write_and_wait(SU(&temp_dir.path().join("a.txt")), "a\n")?;
+        write_and_wait(SU(&temp_dir.path().join("c.log")), "c\n")?;
+
+        // multiple matches are concatenated in sorted order
+        let pattern = temp_dir.path().join("*.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--glob", SU(&pattern), "-", "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+
+        // a single match behaves like a normal file
+        let pattern = temp_dir.path().join("*.log");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--glob", SU(&pattern), "-", "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "c\n");
+
+        // no match is an error
+        let pattern = temp_dir.path().join("*.csv");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--glob", SU(&pattern), "-", "-", "cat"])
+            .output()?;
+        assert_ne!(output.status.code().unwrap(), 0);
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with("o-o: no files match"));
+
+        // without --glob, the pattern is a literal file name
+        let pattern = temp_dir.path().join("*.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&pattern), "-", "-", "cat"])
+            .output()?;
+        assert_ne!(output.status.code().unwrap(), 0);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pty_stdout_is_a_terminal() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--pty", "-", SU(&output_file), "-", "sh", "-c", "if [ -t 1 ]; then echo tty; else echo notty; fi"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "tty\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", SU(&output_file), "-", "sh", "-c", "if [ -t 1 ]; then echo tty; else echo notty; fi"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "notty\n");
+        Ok(())
+    }
+
+    #[test]
+    fn force_color() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+        let script = r#"if [ "$CLICOLOR_FORCE" = 1 ] && [ "$FORCE_COLOR" = 1 ] && [ -z "${NO_COLOR+x}" ]; then printf '\033[31mred\033[0m\n'; else echo plain; fi"#;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--force-color", "-", SU(&output_file), "-", "sh", "-c", script])
+            .env("NO_COLOR", "1")
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "\x1b[31mred\x1b[0m\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", SU(&output_file), "-", "sh", "-c", script])
+            .env("NO_COLOR", "1")
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "plain\n");
+        Ok(())
+    }
+
+    #[test]
+    fn strip_ansi() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+        let error_file = temp_dir.path().join("error.txt");
+        let script = r#"printf '\033[31mred\033[0m\n'; printf '\033[1mbold\033[0m\n' >&2"#;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--strip-ansi", "-", SU(&output_file), SU(&error_file), "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "red\n");
+        assert_eq!(fs::read_to_string(&error_file)?, "\x1b[1mbold\x1b[0m\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--strip-ansi=both", "-", SU(&output_file), SU(&error_file), "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "red\n");
+        assert_eq!(fs::read_to_string(&error_file)?, "bold\n");
+        Ok(())
+    }
+
+    #[test]
+    fn convert_line_endings() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+        let script = r#"printf 'a\nb\r\nc\n'"#;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--crlf", "-", SU(&output_file), "-", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read(&output_file)?, b"a\r\nb\r\nc\r\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--lf", "-", SU(&output_file), "-", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read(&output_file)?, b"a\nb\nc\n");
+
+        // passthrough is left untouched
+        let output = Command::new("./target/debug/o-o")
+            .args(["--crlf", "-", "-", "-", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"a\nb\r\nc\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--lf", "--crlf", "-", "-", "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn strip_bom() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("input.txt");
+        fs::write(&input_file, b"\xEF\xBB\xBFhello\n\xEF\xBB\xBF")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--strip-bom", SU(&input_file), "-", "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n\xEF\xBB\xBF");
+
+        fs::write(&input_file, b"he")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--strip-bom", SU(&input_file), "-", "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"he");
+        Ok(())
+    }
+
+    #[test]
+    fn stderr_on_error() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stderr-on-error", "-", "-", "-", "sh", "-c", "echo out; echo noise >&2"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "out\n");
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stderr-on-error", "-", "-", "-", "sh", "-c", "echo out; echo failed >&2; exit 3"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "out\n");
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "failed\n");
+
+        // only the last bytes are kept
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stderr-on-error=4", "-", "-", "-", "sh", "-c", "printf 'abcdefgh' >&2; exit 1"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert!(String::from_utf8(output.stderr).unwrap().ends_with("efgh"));
+        Ok(())
+    }
+
+    #[test]
+    fn stdout_on_error() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdout-on-error", "-", SU(&output_file), "-", "sh", "-c", "echo build log"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdout-on-error", "-", SU(&output_file), "-", "sh", "-c", "echo build log; exit 2"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        assert_eq!(fs::read_to_string(&output_file)?, "build log\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdout-on-error", "-", "-", "-", "sh", "-c", "echo shown; exit 1"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "shown\n");
+
+        let input_file = temp_dir.path().join("input.txt");
+        write_and_wait(SU(&input_file), "x\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdout-on-error", SU(&input_file), "=", "-", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn keep_going_exit_policy() -> Result<(), io::Error> {
+        let command_lines = ["sh", "-c", "exit 3", "J", "sh", "-c", "exit 5", "J", "sh", "-c", "exit 4", "J", "true"];
+        for (policy, expected) in [(None, 3), (Some("first"), 3), (Some("last"), 4), (Some("max"), 5)] {
+            let mut args = vec!["-k"];
+            if let Some(policy) = policy {
+                args.extend(["--keep-going-exit", policy]);
+            }
+            args.extend(["-", "-", "-"]);
+            args.extend(command_lines);
+            let output = Command::new("./target/debug/o-o").args(&args).output()?;
+            assert_eq!(output.status.code().unwrap(), expected, "policy: {:?}", policy);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn max_parallel() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let file_a = temp_dir.path().join("a.txt");
+        let file_b = temp_dir.path().join("b.txt");
+
+        // the two command lines wait for each other, so that they succeed only when run concurrently
+        let start = std::time::Instant::now();
+        let output = Command::new("./target/debug/o-o")
+            .args(["-k", "--max-parallel", "2", "-", "-", "-",
+                "sh", "-c", &format!("touch {0}; i=0; while [ ! -e {1} ] && [ $i -lt 300 ]; do sleep 0.01; i=$((i+1)); done; [ -e {1} ]", SU(&file_a), SU(&file_b)), "J",
+                "sh", "-c", &format!("touch {0}; i=0; while [ ! -e {1} ] && [ $i -lt 300 ]; do sleep 0.01; i=$((i+1)); done; [ -e {1} ]", SU(&file_b), SU(&file_a))])
+            .output()?;
+        assert!(output.status.success());
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // sharing an output file is rejected
+        let output = Command::new("./target/debug/o-o")
+            .args(["-k", "--max-parallel", "2", "-", SU(&file_a), "-", "echo", "1", "J",
+                "o-o", "-", &format!("+{}", SU(&file_a)), "-", "echo", "2"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        assert!(String::from_utf8(output.stderr).unwrap().contains("share a file"));
+
+        // requires --keep-going
+        let output = Command::new("./target/debug/o-o")
+            .args(["--max-parallel", "2", "-", "-", "-", "true", "J", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn report_json() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let report_file = temp_dir.path().join("report.json");
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&format!("--report-json={}", SU(&report_file)), "-k", "-", "-", "-", "true", "J", "sh", "-c", "exit 3"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 3);
+
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_file)?)?;
+        let entries = report.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["index"], 0);
+        assert_eq!(entries[0]["command"], "true");
+        assert_eq!(entries[0]["exit_code"], 0);
+        assert!(entries[0]["duration_ms"].is_u64());
+        assert_eq!(entries[1]["index"], 1);
+        assert_eq!(entries[1]["command"], "sh -c 'exit 3'");
+        assert_eq!(entries[1]["exit_code"], 3);
+        assert!(entries[1].get("error").is_none());
+
+        // written also on an early exit, to stderr without a file
+        let output = Command::new("./target/debug/o-o")
+            .args(["--report-json", "-", "-", "-", "false", "J", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        let report: serde_json::Value = serde_json::from_slice(&output.stderr)?;
+        let entries = report.as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["command"], "false");
+        assert_eq!(entries[0]["exit_code"], 1);
+        Ok(())
+    }
+
+    #[test]
+    fn report_json_on_deadline() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let report_file = temp_dir.path().join("report.json");
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&format!("--report-json={}", SU(&report_file)), "--deadline=0.5", "-", "-", "-", "true", "J", "sleep", "5", "J", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 124);
+
+        let report: serde_json::Value = serde_json::from_str(&fs::read_to_string(&report_file)?)?;
+        let entries = report.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0]["command"], "true");
+        assert_eq!(entries[0]["exit_code"], 0);
+        assert_eq!(entries[1]["command"], "sleep 5");
+        assert_eq!(entries[1]["exit_code"], 124);
+        assert_eq!(entries[1]["stage_exit_codes"], serde_json::json!([null]));
+        assert!(entries[1]["error"].as_str().unwrap().contains("deadline exceeded"));
+        Ok(())
+    }
+
+    #[test]
+    fn trace_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let trace_file = temp_dir.path().join("trace.log");
+        let output_file = temp_dir.path().join("output.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--trace-file", SU(&trace_file), "-k", "-", SU(&output_file), "-", "echo", "hello world", "J", "sh", "-c", "exit 4"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 4);
+
+        let trace = fs::read_to_string(&trace_file)?;
+        let lines: Vec<&str> = trace.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains(&format!(" exit=0 stdin=- stdout={} stderr=- command=echo 'hello world'", SU(&output_file))));
+        assert!(lines[1].contains(" exit=4 stdin=- stdout=- stderr=- command=sh -c 'exit 4'"));
+        Ok(())
+    }
+
+    #[test]
+    fn no_sync() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("input.txt");
+        let output_file = temp_dir.path().join("output.txt");
+        write_and_wait(SU(&input_file), "b\na\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--no-sync", SU(&input_file), SU(&output_file), "-", "sort"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "a\nb\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--no-sync", SU(&input_file), "=", "-", "sort"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&input_file)?, "a\nb\n");
+        Ok(())
+    }
+
+    #[test]
+    fn append_header() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let log_file = temp_dir.path().join("log.txt");
+        let log_arg = format!("+{}", SU(&log_file));
+
+        for message in ["first", "second"] {
+            let output = Command::new("./target/debug/o-o")
+                .args(["--append-header", "=== run at %t ===", "-", &log_arg, "-", "echo", message])
+                .output()?;
+            assert!(output.status.success());
+        }
+
+        let log = fs::read_to_string(&log_file)?;
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].starts_with("=== run at ") && lines[0].ends_with(" ===") && !lines[0].contains("%t"));
+        assert_eq!(lines[1], "first");
+        assert!(lines[2].starts_with("=== run at "));
+        assert_eq!(lines[3], "second");
+
+        // not applied when overwriting
+        let output_file = temp_dir.path().join("output.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--append-header", "header", "-", SU(&output_file), "-", "echo", "body"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "body\n");
+        Ok(())
+    }
+
+    #[test]
+    fn options_from_oo_args() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "echo", "hi"])
+            .env("OO_ARGS", "--label='a b,c'")
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a b: hi\n");
+
+        // the options in the command line override the ones in OO_ARGS
+        let output = Command::new("./target/debug/o-o")
+            .args(["--label=x,y", "-", "-", "-", "echo", "hi"])
+            .env("OO_ARGS", "--label='a b,c'")
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "x: hi\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "true"])
+            .env("OO_ARGS", "--label='a")
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn dump_config() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-s", "X", "--dump-config"])
+            .env("OO_ARGS", "-p P -s S")
+            .output()?;
+        assert!(output.status.success());
+
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert!(lines.contains(&"OO_ARGS = Some(\"-p P -s S\")"));
+        assert!(lines.contains(&"pipe = \"P\""));
+        assert!(lines.contains(&"separator = \"X\""));
+        assert!(lines.contains(&"tempdir_placeholder = \"T\""));
+        Ok(())
+    }
+
+    #[test]
+    fn allow_empty_segments() -> Result<(), io::Error> {
+        for command_line in [vec!["J", "echo", "a", "J", "echo", "b"], vec!["echo", "a", "J", "J", "echo", "b", "J"]] {
+            let mut args = vec!["--allow-empty-segments", "-", "-", "-"];
+            args.extend(&command_line);
+            let output = Command::new("./target/debug/o-o").args(&args).output()?;
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+
+            let mut args = vec!["-", "-", "-"];
+            args.extend(&command_line);
+            let output = Command::new("./target/debug/o-o").args(&args).output()?;
+            assert_eq!(output.status.code().unwrap(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn comment_tokens() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--comment", "-", "-", "-", "echo", "a", "J", "#", "false", "I", "cat", "J", "echo", "b", "#", "trailing"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--comment=REM", "-", "-", "-", "echo", "a", "REM", "b"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\n");
+        Ok(())
+    }
+
+    #[test]
+    fn spawn_limit() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--spawn-limit", "3", "-", "-", "-", "echo", "a", "J", "echo", "b", "I", "cat", "J", "echo", "c"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+        assert!(String::from_utf8(output.stderr).unwrap().contains("spawn limit reached"));
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--spawn-limit", "4", "-", "-", "-", "echo", "a", "J", "echo", "b", "I", "cat", "J", "echo", "c"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\nc\n");
+        Ok(())
+    }
+
+    #[test]
+    fn commands_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let commands_file = temp_dir.path().join("commands.txt");
+        write_and_wait(SU(&commands_file), "echo a\n\n# echo commented\n  echo 'b c' I tr b x\nfalse\necho d\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--commands-file", SU(&commands_file), "-", "-", "-"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nx c\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--commands-file", SU(&commands_file), "-k", "-", "-", "-"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nx c\nd\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--commands-file", SU(&commands_file), "-", "-", "-", "echo", "e"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn convert_without_command() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let dos_file = temp_dir.path().join("dos.txt");
+        let unix_file = temp_dir.path().join("unix.txt");
+        write_and_wait(SU(&dos_file), "a\r\nb\r\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--convert=dos2unix", SU(&dos_file), SU(&unix_file), "-"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&unix_file)?, "a\nb\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--convert=unix2dos", SU(&unix_file), "-", "-"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\r\nb\r\n");
+
+        // in-place overwrite
+        let output = Command::new("./target/debug/o-o")
+            .args(["--convert=unix2dos", SU(&unix_file), "=", "-"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&unix_file)?, "a\r\nb\r\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--convert=dos2unix", SU(&dos_file), "=", "-"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&dos_file)?, "a\nb\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--convert=dos2unix", SU(&dos_file), "-", "-", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn skip_if_fresh() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("in.txt");
+        let output_file = temp_dir.path().join("out.txt");
+        write_and_wait(SU(&input_file), "a\n")?;
+        thread::sleep(Duration::from_millis(50));
+        write_and_wait(SU(&output_file), "fresh\n")?;
+
+        // fresh: the command is skipped
+        let output = Command::new("./target/debug/o-o")
+            .args(["--skip-if-fresh", SU(&input_file), SU(&output_file), "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "fresh\n");
+
+        // stale: the command is run
+        thread::sleep(Duration::from_millis(50));
+        write_and_wait(SU(&input_file), "b\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--skip-if-fresh", SU(&input_file), SU(&output_file), "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn clear_env_with_passthrough() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .env("HOME", "/home/oo-test")
+            .env("OO_TEST_VAR", "parent")
+            .args(["--clear-env", "--env-passthrough", "HOME", "-e", "A=1", "-", "-", "-", "/usr/bin/env"])
+            .output()?;
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let mut lines: Vec<&str> = stdout.lines().collect();
+        lines.sort();
+        assert_eq!(lines, vec!["A=1", "HOME=/home/oo-test"]);
+
+        // without --clear-env, --env-passthrough is a no-op
+        let output = Command::new("./target/debug/o-o")
+            .env("OO_TEST_VAR", "parent")
+            .args(["--env-passthrough", "HOME", "-", "-", "-", "/usr/bin/env"])
+            .output()?;
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stdout).unwrap().lines().any(|l| l == "OO_TEST_VAR=parent"));
+        Ok(())
+    }
+
+    #[test]
+    fn sub_oo_relative_working_directory() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        fs::create_dir_all(temp_dir.path().join("a/b"))?;
+        let output_file = temp_dir.path().join("output.txt");
+        let append_file = format!("+{}", SU(&output_file));
+
+        // sub-command's relative -d is resolved against the parent's -d
+        let a_dir = temp_dir.path().join("a");
+        let output = Command::new("./target/debug/o-o")
+            .args([
+                "-d", SU(&a_dir), "-", SU(&output_file), "-", "pwd",
+                "J", "o-o", "-d", "b", "-", &append_file, "-", "pwd",
+                "J", "o-o", "-", &append_file, "-", "pwd",
+            ])
+            .output()?;
+        assert!(output.status.success());
+        let a_dir = fs::canonicalize(&a_dir)?;
+        let b_dir = fs::canonicalize(temp_dir.path().join("a/b"))?;
+        let expected = format!("{}\n{}\n{}\n", a_dir.display(), b_dir.display(), a_dir.display());
+        assert_eq!(fs::read_to_string(&output_file)?, expected);
+
+        // without the parent's -d, `b` is resolved against the current directory, where it does not exist
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "true", "J", "o-o", "-d", "b", "-", "-", "-", "pwd"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn deadline() -> Result<(), io::Error> {
+        let start = std::time::Instant::now();
+        let output = Command::new("./target/debug/o-o")
+            .args(["--deadline", "1.5", "-k", "-", "-", "-", "sh", "-c", "sleep 1; echo a", "J", "sleep", "1", "J", "echo", "c"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 124);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\n");
+        assert!(String::from_utf8(output.stderr).unwrap().contains("deadline exceeded"));
+        assert!(start.elapsed() < Duration::from_millis(1900));
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--deadline", "10", "-", "-", "-", "echo", "a", "J", "echo", "b"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+        Ok(())
+    }
+
+    #[test]
+    fn exit_code_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let exit_code_file = temp_dir.path().join("exit_code.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--exit-code-file", SU(&exit_code_file), "-", "-", "-", "sh", "-c", "exit 12"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 12);
+        assert_eq!(fs::read_to_string(&exit_code_file)?, "12\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--exit-code-file", SU(&exit_code_file), "-", "-", "-", "true"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&exit_code_file)?, "0\n");
+        Ok(())
+    }
+
+    #[test]
+    fn exit_code_file_on_deadline() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let exit_code_file = temp_dir.path().join("exit_code.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--exit-code-file", SU(&exit_code_file), "--deadline=0.2", "-", "-", "-", "sleep", "5"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 124);
+        assert_eq!(fs::read_to_string(&exit_code_file)?, "124\n");
+        Ok(())
+    }
+
+    #[test]
+    fn stderr_to_named_stdout_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+        write_and_wait(SU(&output_file), "old content which is longer than the output\n")?;
+        let script = "echo out; echo err >&2; echo out2; echo err2 >&2";
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", SU(&output_file), "=", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "out\nerr\nout2\nerr2\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", &format!("+{}", SU(&output_file)), "=", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&output_file)?, "out\nerr\nout2\nerr2\n".repeat(2));
+        Ok(())
+    }
+
+    #[test]
+    fn retries() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let count_file = temp_dir.path().join("count.txt");
+        // fails until the third attempt
+        let script = format!("echo x >> {}; [ $(wc -l < {}) -ge 3 ]", SU(&count_file), SU(&count_file));
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--retries", "1", "--retry-delay", "10", "-", "-", "-", "sh", "-c", &script])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(fs::read_to_string(&count_file)?.lines().count(), 2);
+
+        fs::remove_file(&count_file)?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--retries", "5", "--retry-delay", "10", "--retry-jitter", "5", "-", "-", "-", "sh", "-c", &script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&count_file)?.lines().count(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn dry_run_checks_commands() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--dry-run", "-", SU(&output_file), "-", "echo", "a", "I", "no-such-command-of-o-o", "J", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 127);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "echo a | no-such-command-of-o-o\ncat\n");
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("command not found: no-such-command-of-o-o"));
+        assert!(!stderr.contains("echo") && !stderr.contains("cat"));
+        assert!(!output_file.exists());
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--dry-run", "-", "-", "-", "echo", "a"])
+            .output()?;
+        assert!(output.status.success());
+        Ok(())
+    }
+
+    #[test]
+    fn stdin_null() -> Result<(), io::Error> {
+        // stdin of o-o is kept open, so `cat` would block on it without --stdin-null
+        let mut child = Command::new("./target/debug/o-o")
+            .args(["--stdin-null", "-", "-", "-", "cat"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let _stdin = child.stdin.take();
+        let start = std::time::Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > Duration::from_secs(5) {
+                child.kill()?;
+                panic!("cat with --stdin-null did not complete");
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(status.success());
+
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("input.txt");
+        write_and_wait(SU(&input_file), "a\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdin-null", SU(&input_file), "-", "-", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    #[ignore] // requires the root privilege
+    fn chroot() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--chroot=/", "-d", "tmp", "-", "-", "-", "pwd"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "/tmp\n");
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn argv0() -> Result<(), io::Error> {
+        // cat prints its own command line, whose first element is argv[0]
+        let output = Command::new("./target/debug/o-o")
+            .args(["--argv0=my-cat", "-", "-", "-", "cat", "/proc/self/cmdline"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"my-cat\0/proc/self/cmdline\0");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "cat", "/proc/self/cmdline"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"cat\0/proc/self/cmdline\0");
+        Ok(())
+    }
+
+    #[test]
+    fn no_inherit_stdin() -> Result<(), io::Error> {
+        // stdin of o-o is kept open, so `cat` sees EOF only when it does not inherit the stdin
+        let mut child = Command::new("./target/debug/o-o")
+            .args(["--no-inherit-stdin", "-", "-", "-", "cat"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()?;
+        let _stdin = child.stdin.take();
+        let start = std::time::Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if start.elapsed() > Duration::from_secs(5) {
+                child.kill()?;
+                panic!("cat with --no-inherit-stdin did not complete");
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert!(status.success());
+
+        // a stdin file is still read
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("input.txt");
+        write_and_wait(SU(&input_file), "a\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--no-inherit-stdin", SU(&input_file), "-", "-", "cat"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\n");
+        Ok(())
+    }
+
+    #[test]
+    fn nested_oo_without_command() -> Result<(), io::Error> {
+        for nested in [vec!["o-o", "-", "-", "-"], vec!["o-o"]] {
+            let mut args = vec!["-", "-", "-", "echo", "a", "J"];
+            args.extend(&nested);
+            let output = Command::new("./target/debug/o-o").args(&args).output()?;
+            assert_eq!(output.status.code().unwrap(), 2);
+            assert!(output.stdout.is_empty());
+            assert_eq!(String::from_utf8(output.stderr).unwrap(), "o-o: nested o-o has no command\n");
+        }
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn inherited_fds() -> Result<(), io::Error> {
+        // the read end of the pipe is passed to o-o as fd 3, and the stdin of o-o is closed otherwise
+        let output = Command::new("sh")
+            .args(["-c", "echo hello | ./target/debug/o-o --stdin-fd=3 - - - cat 3<&0 0</dev/null"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "hello\n");
+
+        let temp_dir = tempdir()?;
+        let output_file = temp_dir.path().join("output.txt");
+        let script = format!("./target/debug/o-o --stdout-fd=4 --stderr-fd=4 - - - sh -c 'echo out; echo err >&2' 4>{}", SU(&output_file));
+        let output = Command::new("sh").args(["-c", &script]).output()?;
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        assert_eq!(fs::read_to_string(&output_file)?, "out\nerr\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdout-fd=1", "-", SU(&output_file), "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn explain() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("a.txt");
+        let output_file = temp_dir.path().join("b.txt");
+        write_and_wait(SU(&input_file), "a\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--explain", SU(&input_file), SU(&output_file), ".", "cat", "I", "wc", "-l"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!("reads stdin from {}, writes stdout to {} (overwrite), discards stderr, runs: cat | wc -l\n", SU(&input_file), SU(&output_file))
+        );
+        assert!(!output_file.exists());
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--explain", "-", &format!("+{}", SU(&output_file)), "=", "echo", "a b", "J", "o-o", SU(&input_file), "=", "-", "sort"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "1. inherits stdin, writes stdout to {} (append), merges stderr into stdout, runs: echo 'a b'\n\
+                 2. reads stdin from {}, writes stdout back to the stdin file (overwrite), inherits stderr, runs: sort\n",
+                SU(&output_file), SU(&input_file)
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn overwrite_failure_is_reported() -> Result<(), io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir()?;
+        let dir = temp_dir.path().join("readonly");
+        fs::create_dir(&dir)?;
+        let target = dir.join("a.txt");
+        write_and_wait(SU(&target), "b\na\n")?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555))?;
+
+        // the root user can remove files in a read-only directory, so a file of procfs is used instead
+        let writable_anyway = File::create(dir.join("probe")).is_ok();
+        let target = if writable_anyway { Path::new("/proc/version").to_path_buf() } else { target };
+        if writable_anyway && !target.exists() {
+            fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+            return Ok(());
+        }
+
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&target), "=", "-", "sort"])
+            .output()?;
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755))?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.starts_with(&format!("o-o: failed to replace {}: ", SU(&target))), "{}", stderr);
+        Ok(())
+    }
+
+    #[test]
+    fn fd_options_equivalent_to_positionals() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let input_file = temp_dir.path().join("input.txt");
+        let output_file = temp_dir.path().join("output.txt");
+        let error_file = temp_dir.path().join("error.txt");
+        write_and_wait(SU(&input_file), "b\na\n")?;
+        let script = "sort; echo err >&2";
+
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&input_file), SU(&output_file), SU(&error_file), "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        let positional = (fs::read_to_string(&output_file)?, fs::read_to_string(&error_file)?);
+        fs::remove_file(&output_file)?;
+        fs::remove_file(&error_file)?;
+
+        let stdin_option = format!("--stdin={}", SU(&input_file));
+        let output = Command::new("./target/debug/o-o")
+            .args([&stdin_option, "--stderr", SU(&error_file), "--stdout", SU(&output_file), "--", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!((fs::read_to_string(&output_file)?, fs::read_to_string(&error_file)?), positional);
+        assert_eq!(positional, ("a\nb\n".to_string(), "err\n".to_string()));
+
+        // only stderr by the option
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stderr=.", SU(&input_file), "-", "sh", "-c", script])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\nb\n");
+        assert!(output.stderr.is_empty());
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&stdin_option, SU(&input_file), "-", "-", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn process_substitution() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "wc", "-l", "<(printf 'a\\nb (c)\\n')"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "2");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "-", "-", "cat", "<(echo a"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn null_io() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--null-io", "--", "sh", "-c", "cat; echo out; echo err >&2; exit 3"])
+            .stdin(std::process::Stdio::piped())
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        assert!(output.stdout.is_empty());
+        assert!(output.stderr.is_empty());
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--null-io", "-", "out.txt", "-", "echo", "a"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_with_large_output() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("data.txt");
+        let data: String = (0..200_000).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(&path, &data)?;
+
+        let status = Command::new(std::fs::canonicalize("./target/debug/o-o")?)
+            .args(["data.txt", "=", "-", "sh", "-c", "cat; cat data.txt"])
+            .current_dir(temp_dir.path())
+            .status()?;
+        assert!(status.success());
+        let overwritten = std::fs::read_to_string(&path)?;
+        let expected = data.repeat(2);
+        assert_eq!(overwritten.len(), expected.len());
+        assert_eq!(overwritten, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn ensure_trailing_newline() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.txt");
+        for (printed, expected) in [("a", "a\n"), ("a\n", "a\n"), ("", "")] {
+            let status = Command::new("./target/debug/o-o")
+                .args(["--ensure-trailing-newline", "-", path.to_str().unwrap(), "-", "printf", printed])
+                .status()?;
+            assert!(status.success());
+            assert_eq!(std::fs::read_to_string(&path)?, expected);
+        }
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--ensure-trailing-newline", "-", &format!("+{}", path.to_str().unwrap()), "-", "printf", "b"])
+            .status()?;
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&path)?, "b\n");
+        Ok(())
+    }
+
+    #[test]
+    fn atomic_stdout() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.txt");
+        std::fs::write(&path, "old\n")?;
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--atomic-stdout", "-", path.to_str().unwrap(), "-", "sh", "-c", "echo partial; exit 1"])
+            .status()?;
+        assert_eq!(status.code().unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&path)?, "old\n");
+        assert_eq!(std::fs::read_dir(temp_dir.path())?.count(), 1); // no temporary file is left
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--atomic-stdout", "-", path.to_str().unwrap(), "-", "echo", "new"])
+            .status()?;
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&path)?, "new\n");
+
+        // stderr of `=` goes to the same file
+        let output = Command::new("./target/debug/o-o")
+            .args(["--atomic-stdout", "-", path.to_str().unwrap(), "=", "sh", "-c", "echo out; echo err >&2"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(std::fs::read_to_string(&path)?, "out\nerr\n");
+        assert!(output.stdout.is_empty());
+
+        // only for the stdout file of the first command line
+        let output = Command::new("./target/debug/o-o")
+            .args(["--atomic-stdout", "-", path.to_str().unwrap(), "-", "echo", "a", "J", "echo", "b"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(std::fs::read_to_string(&path)?, "a\n");
+        assert_eq!(output.stdout, b"b\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--atomic-stdout", "-", "-", "-", "echo", "new"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn same_file_for_stdout_and_stderr() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["-", "out.txt", "+out.txt", "echo", "a"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert!(stderr.contains("<stdout> and <stderr> are the same file: out.txt"), "{}", stderr);
+        assert!(stderr.contains("Use `=` for <stderr> to merge it into <stdout>"), "{}", stderr);
+        assert!(!std::path::Path::new("out.txt").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn large_stdin_with_bounded_memory() -> Result<(), io::Error> {
+        const SIZE: u64 = 300 * 1024 * 1024;
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("large.bin");
+        std::fs::File::create(&path)?.set_len(SIZE)?; // a sparse file, read as zeros
+
+        // The address space is limited below the size of the input, so buffering it as a whole would fail
+        let o_o = std::fs::canonicalize("./target/debug/o-o")?;
+        let script = format!("ulimit -v 262144; {} --strip-bom {} - - wc -c", o_o.display(), path.display());
+        let output = Command::new("sh").args(["-c", &script]).output()?;
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), SIZE.to_string());
+        Ok(())
+    }
+
+    #[test]
+    fn report_lines() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.txt");
+        for (printed, lines) in [("a\nb\nc\n", 3), ("a\nb", 2), ("", 0)] {
+            let output = Command::new("./target/debug/o-o")
+                .args(["--report-lines", "-", path.to_str().unwrap(), "-", "printf", printed])
+                .output()?;
+            assert!(output.status.success());
+            let stderr = String::from_utf8(output.stderr).unwrap();
+            assert_eq!(stderr, format!("o-o: wrote {} lines to {}\n", lines, path.display()));
+            let content = std::fs::read_to_string(&path)?;
+            assert_eq!(content.lines().count(), lines);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn abort_on_stderr() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--abort-on-stderr", "--", "sh", "-c", "echo warning >&2; exit 0"])
+            .output()?;
+        assert_ne!(output.status.code().unwrap(), 0);
+        assert!(String::from_utf8(output.stderr).unwrap().starts_with("warning\n"));
+
+        let start = std::time::Instant::now();
+        let output = Command::new("./target/debug/o-o")
+            .args(["--abort-on-stderr", "--", "sh", "-c", "echo warning >&2; exec sleep 5"])
+            .output()?;
+        assert_ne!(output.status.code().unwrap(), 0);
+        assert!(start.elapsed() < std::time::Duration::from_secs(4));
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--abort-on-stderr", "--", "echo", "a"])
+            .output()?;
+        assert!(output.status.success());
+        Ok(())
+    }
+
+    #[test]
+    fn env_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let dotenv = temp_dir.path().join("vars.env");
+        std::fs::write(&dotenv, "A=\"x y\"\nB=file\n")?;
+        let json = temp_dir.path().join("vars.json");
+        std::fs::write(&json, "{\"A\": \"x y\", \"B\": \"file\"}")?;
+
+        for file in [&dotenv, &json] {
+            let output = Command::new("./target/debug/o-o")
+                .args(["--env-file", file.to_str().unwrap(), "-e", "B=option", "--", "sh", "-c", "echo \"$A/$B\""])
+                .output()?;
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stdout).unwrap(), "x y/option\n");
+        }
+
+        std::fs::write(&dotenv, "A=\"x\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--env-file", dotenv.to_str().unwrap(), "--", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        assert!(String::from_utf8(output.stderr).unwrap().contains("line 1, column 3"));
+        Ok(())
+    }
+
+    #[test]
+    fn touch_output() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let out = temp_dir.path().join("out.txt");
+        let err = temp_dir.path().join("err.txt");
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--touch-output", "-", out.to_str().unwrap(), err.to_str().unwrap(), "true"])
+            .status()?;
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&out)?, "");
+        assert_eq!(std::fs::read_to_string(&err)?, "");
+
+        std::fs::remove_file(&out)?;
+        let status = Command::new("./target/debug/o-o")
+            .args(["--touch-output", "--atomic-stdout", "-", out.to_str().unwrap(), "-", "false"])
+            .status()?;
+        assert_eq!(status.code().unwrap(), 1);
+        assert_eq!(std::fs::read_to_string(&out)?, "");
+        Ok(())
+    }
+
+    #[test]
+    fn require_commands() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let marker = temp_dir.path().join("ran");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--require=sh", "--require", "o-o-no-such-command", "--", "touch", marker.to_str().unwrap()])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 127);
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "o-o: required command not found: o-o-no-such-command\n");
+        assert!(!marker.exists());
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["--require=sh", "--", "touch", marker.to_str().unwrap()])
+            .status()?;
+        assert!(status.success());
+        assert!(marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn signal_on_timeout() -> Result<(), io::Error> {
+        let script = "trap 'echo got HUP; exit 0' HUP; while :; do sleep 0.1; done";
+        let output = Command::new("./target/debug/o-o")
+            .args(["--deadline=0.5", "--signal-on-timeout=HUP", "--", "sh", "-c", script])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 124);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "got HUP\n");
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--signal-on-timeout=NOSUCH", "--deadline=1", "--", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn stdout_hash() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("out.txt");
+        // the digests of "hello", as computed by sha256sum and md5sum
+        for (algorithm, digest) in [
+            ("sha256", "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"),
+            ("md5", "5d41402abc4b2a76b9719d911017c592"),
+        ] {
+            let output = Command::new("./target/debug/o-o")
+                .args([&format!("--stdout-hash={}", algorithm), "-", path.to_str().unwrap(), "-", "printf", "hello"])
+                .output()?;
+            assert!(output.status.success());
+            assert_eq!(String::from_utf8(output.stderr).unwrap(), format!("o-o: stdout {} = {}\n", algorithm, digest));
+            assert_eq!(std::fs::read_to_string(&path)?, "hello");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn token_regex() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--token-regex", "-p", "I|PIPE", "-", "-", "-", "printf", "b\\na\\n", "I", "sort", "PIPE", "head", "-n1"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "a\n");
+        Ok(())
+    }
+
+    #[test]
+    fn stdin_json() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let path = temp_dir.path().join("records.json");
+        std::fs::write(&path, "[\n  {\"id\": 1},\n  {\"id\": 2, \"tags\": [\"a\", \"b\"]},\n  {\"id\": 3}\n]\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdin-json", path.to_str().unwrap(), "--", "wc", "-l"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap().trim(), "3");
+
+        std::fs::write(&path, "[{\"id\": 1},")?;
+        let marker = temp_dir.path().join("ran");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--stdin-json", path.to_str().unwrap(), "--", "touch", marker.to_str().unwrap()])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        assert!(!marker.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn output_template() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let output = Command::new(std::fs::canonicalize("./target/debug/o-o")?)
+            .args(["--output-template=out-%n.txt", "-", "-", "-", "echo", "a", "J", "echo", "b", "J", "echo", "c"])
+            .current_dir(temp_dir.path())
+            .output()?;
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        for (n, content) in [(1, "a\n"), (2, "b\n"), (3, "c\n")] {
+            assert_eq!(std::fs::read_to_string(temp_dir.path().join(format!("out-{}.txt", n)))?, content);
+        }
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--output-template=out.txt", "-", "-", "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn report_json_stage_exit_codes() -> Result<(), io::Error> {
+        let output = Command::new("./target/debug/o-o")
+            .args(["--report-json", "-", ".", "-", "true", "I", "sh", "-c", "exit 4", "I", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 4);
+        let report: serde_json::Value = serde_json::from_slice(&output.stderr)?;
+        assert_eq!(report[0]["stage_exit_codes"], serde_json::json!([0, 4, 0]));
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dereference_stdin() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let target = temp_dir.path().join("data.txt");
+        let link = temp_dir.path().join("link.txt");
+
+        // by default, the link is replaced with a regular file, and the file linked to is left as is
+        fs::write(&target, "b\na\n")?;
+        std::os::unix::fs::symlink(&target, &link)?;
+        let status = Command::new("./target/debug/o-o")
+            .args([SU(&link), "=", "-", "sort"])
+            .status()?;
+        assert!(status.success());
+        assert!(!fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&link)?, "a\nb\n");
+        assert_eq!(fs::read_to_string(&target)?, "b\na\n");
+
+        // with the option, the file linked to is overwritten through the link
+        fs::remove_file(&link)?;
+        std::os::unix::fs::symlink(&target, &link)?;
+        let status = Command::new("./target/debug/o-o")
+            .args(["--dereference-stdin", SU(&link), "=", "-", "sort"])
+            .status()?;
+        assert!(status.success());
+        assert!(fs::symlink_metadata(&link)?.file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target)?, "a\nb\n");
+
+        // a dangling link is reported as a missing stdin file
+        fs::remove_file(&target)?;
+        let output = Command::new("./target/debug/o-o")
+            .args(["--dereference-stdin", SU(&link), "=", "-", "sort"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        assert_eq!(stderr.trim_end(), format!("o-o: cannot read stdin file: {} (No such file or directory)", SU(&link)));
+        Ok(())
+    }
+
+    #[test]
+    fn setup_and_teardown() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let main_marker = temp_dir.path().join("main");
+        let teardown_marker = temp_dir.path().join("teardown");
+
+        // a failing setup prevents the command lines (even with --keep-going) and the teardown
+        let output = Command::new("./target/debug/o-o")
+            .args(["-k", "--setup=sh -c 'exit 5'", &format!("--teardown=touch {}", SU(&teardown_marker)), "-", "-", "-", "touch", SU(&main_marker)])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 5);
+        assert!(String::from_utf8(output.stderr).unwrap().contains("o-o: setup command failed"));
+        assert!(!main_marker.exists());
+        assert!(!teardown_marker.exists());
+
+        // the teardown runs after the command lines, also when they fail
+        let output = Command::new("./target/debug/o-o")
+            .args(["--setup=true", &format!("--teardown=touch {}", SU(&teardown_marker)), "-", "-", "-", "sh", "-c", "exit 3"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        assert!(teardown_marker.exists());
+
+        // which see the environment variables given to the command lines
+        let output = Command::new("./target/debug/o-o")
+            .args(["-e", "NAME=value", "--setup=sh -c 'echo setup $NAME'", "--teardown=sh -c 'echo teardown $NAME'", "-", "-", "-", "echo", "main"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "setup value\nmain\nteardown value\n");
+        Ok(())
+    }
+
+    #[test]
+    fn overwrite_input_file_through_pipe() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let file = temp_dir.path().join("file.txt");
+
+        // the file is replaced with the output of the last command, after the whole pipe has finished
+        fs::write(&file, "abc\nbad\n")?;
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&file), "=", "-", "cat", SU(&file), "I", "tr", "a", "x", "I", "sort", "-r"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&file)?, "xbc\nbxd\n");
+
+        // a failure of a stage of the pipe leaves the file as is
+        let output = Command::new("./target/debug/o-o")
+            .args([SU(&file), "=", "-", "cat", SU(&file), "I", "sh", "-c", "cat; exit 4", "I", "tr", "x", "y"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 4);
+        assert_eq!(fs::read_to_string(&file)?, "xbc\nbxd\n");
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn chmod_and_umask() -> Result<(), io::Error> {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = tempdir()?;
+        let out = temp_dir.path().join("out.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--chmod=0o600", "-", SU(&out), "-", "echo", "a"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::metadata(&out)?.permissions().mode() & 0o7777, 0o600);
+
+        let created = temp_dir.path().join("created.txt");
+        let output = Command::new("./target/debug/o-o")
+            .args(["--umask=077", "-", "-", "-", "touch", SU(&created)])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::metadata(&created)?.permissions().mode() & 0o777, 0o600);
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["--chmod=999", "-", "-", "-", "true"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn working_directory_sets_pwd() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let script = temp_dir.path().join("pwd.sh");
+        fs::write(&script, "echo \"$PWD\"\necho \"$OLDPWD\"\n")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args(["-d", SU(temp_dir.path()), "-", "-", "-", "sh", SU(&script)])
+            .output()?;
+        assert!(output.status.success());
+        let cwd = std::env::current_dir()?;
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n{}\n", SU(temp_dir.path()), SU(&cwd)));
+
+        // the one given by -e takes precedence
+        let output = Command::new("./target/debug/o-o")
+            .args(["-d", SU(temp_dir.path()), "-e", "PWD=/given", "-", "-", "-", "printenv", "PWD"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "/given\n");
+        Ok(())
+    }
+
+    #[test]
+    fn and_or_separators() -> Result<(), io::Error> {
+        let run = |args: &[&str]| Command::new("./target/debug/o-o")
+            .args(["--and-separator=AND", "--or-separator=OR", "-", "-", "-"])
+            .args(args)
+            .output();
+
+        let output = run(&["true", "AND", "echo", "and-run", "OR", "echo", "or-skipped"])?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "and-run\n");
+
+        // a failure recovered by the or-separator does not fail o-o
+        let output = run(&["false", "AND", "echo", "and-skipped", "OR", "echo", "or-run"])?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "or-run\n");
+
+        // while not recovered, it stops the command lines after the separator
+        let output = run(&["sh", "-c", "exit 3", "AND", "echo", "and-skipped", "J", "echo", "stopped"])?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        assert!(output.stdout.is_empty());
+
+        let output = run(&["-k", "sh", "-c", "exit 3", "AND", "echo", "and-skipped", "J", "echo", "kept-going"])?;
+        assert_eq!(output.status.code().unwrap(), 3);
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "kept-going\n");
+        Ok(())
+    }
+
+    #[test]
+    fn capture_to_variable_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let file = temp_dir.path().join("vars.sh");
+        let capture = format!("--capture=NAME:{}", SU(&file));
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&capture, "-", "-", "-", "echo", "value"])
+            .output()?;
+        assert!(output.status.success());
+        assert!(output.stdout.is_empty());
+        let output = Command::new("./target/debug/o-o")
+            .args(["--capture", &format!("OTHER:{}", SU(&file)), "-", "-", "-", "printf", "it's\\n\\n"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(fs::read_to_string(&file)?, "NAME=value\nOTHER='it'\\''s'\n");
+
+        // nothing is written by a failure
+        let output = Command::new("./target/debug/o-o")
+            .args([&capture, "-", "-", "-", "sh", "-c", "echo x; exit 1"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 1);
+        assert_eq!(fs::read_to_string(&file)?.lines().count(), 2);
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&capture, "-", "-", "-", "echo", "a", "I", "cat"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn args0_file() -> Result<(), io::Error> {
+        let temp_dir = tempdir()?;
+        let file = temp_dir.path().join("args0");
+        fs::write(&file, "printf\0[%s]\\n\0with space\0a | b\0")?;
+
+        let output = Command::new("./target/debug/o-o")
+            .args([&format!("--args0-file={}", SU(&file)), "-", "-", "-", "last"])
+            .output()?;
+        assert!(output.status.success());
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), "[with space]\n[a | b]\n[last]\n");
+
+        fs::write(&file, "")?;
+        let output = Command::new("./target/debug/o-o")
+            .args([&format!("--args0-file={}", SU(&file)), "-", "-", "-"])
+            .output()?;
+        assert_eq!(output.status.code().unwrap(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn progress_not_shown_without_terminal() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let stderr_file = temp_dir.path().join("stderr.txt");
+
+        // Neither the piped stderr of o-o nor the stderr file of the command receives the spinner
+        let output = Command::new("./target/debug/o-o").args(["--progress", "-", "-", SU(&stderr_file), "sh", "-c", "sleep 0.3; echo done"]).output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"done\n");
+        assert!(output.stderr.is_empty());
+        assert_eq!(fs::read(&stderr_file)?, b"");
+
+        Ok(())
+    }
+
+    #[test]
+    fn env_without_value_inherits_variable() -> anyhow::Result<()> {
+        let output = Command::new("./target/debug/o-o").env("OO_TEST_PRESENT", "yes").env_remove("OO_TEST_ABSENT")
+            .args(["--clear-env", "-e", "OO_TEST_PRESENT", "-e", "OO_TEST_ABSENT", "-e", "PATH", "-", "-", "-", "env"]).output()?;
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout)?;
+        assert!(stdout.lines().any(|line| line == "OO_TEST_PRESENT=yes"));
+        assert!(!stdout.contains("OO_TEST_ABSENT"));
+
+        // The later option for a variable wins, as with -e VAR=VALUE and -u
+        for args in [["-u", "OO_TEST_PRESENT", "-e", "OO_TEST_PRESENT"], ["-e", "OO_TEST_PRESENT=x", "-e", "OO_TEST_PRESENT"]] {
+            let output = Command::new("./target/debug/o-o").env("OO_TEST_PRESENT", "yes")
+                .args(args).args(["-", "-", "-", "printenv", "OO_TEST_PRESENT"]).output()?;
+            assert!(output.status.success(), "{:?}", args);
+            assert_eq!(String::from_utf8(output.stdout)?, "yes\n", "{:?}", args);
+        }
+        let output = Command::new("./target/debug/o-o").env("OO_TEST_PRESENT", "yes")
+            .args(["-e", "OO_TEST_PRESENT", "-u", "OO_TEST_PRESENT", "-", "-", "-", "printenv", "OO_TEST_PRESENT"]).output()?;
+        assert_eq!(output.status.code(), Some(1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn lock_file_serializes_runs() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let lock = temp_dir.path().join("lock");
+        let spawn = |name: &str| {
+            // Each run records the times (in nanoseconds) it starts and ends
+            let times = temp_dir.path().join(name);
+            Command::new("./target/debug/o-o").args(["--lock-file", SU(&lock), "-", SU(&times), "-", "sh", "-c", "date +%s%N; sleep 0.3; date +%s%N"]).spawn()
+        };
+        let mut first = spawn("first.txt")?;
+        let mut second = spawn("second.txt")?;
+        assert!(first.wait()?.success());
+        assert!(second.wait()?.success());
+
+        let read_times = |name: &str| -> anyhow::Result<(u128, u128)> {
+            let text = fs::read_to_string(temp_dir.path().join(name))?;
+            let times: Vec<u128> = text.lines().map(|line| line.parse()).collect::<Result<_, _>>()?;
+            Ok((times[0], times[1]))
+        };
+        let (first_start, first_end) = read_times("first.txt")?;
+        let (second_start, second_end) = read_times("second.txt")?;
+        assert!(first_end <= second_start || second_end <= first_start);
+
+        // Another run gives up while the lock is held
+        let mut holder = spawn("holder.txt")?;
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        let output = Command::new("./target/debug/o-o").args(["--lock-file", SU(&lock), "--lock-timeout", "0.05", "-", "-", "-", "true"]).output()?;
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr)?.contains("timed out waiting for the lock"));
+        assert!(holder.wait()?.success());
+
+        Ok(())
+    }
+
+    #[test]
+    fn stdin_tee() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let input = temp_dir.path().join("input.bin");
+        let tee = temp_dir.path().join("tee.bin");
+        let received = temp_dir.path().join("received.bin");
+        let bytes: Vec<u8> = (0..20000).map(|i| (i % 251) as u8).collect();
+        fs::write(&input, &bytes)?;
+
+        let status = Command::new("./target/debug/o-o").args(["--stdin-tee", SU(&tee), SU(&input), SU(&received), "-", "cat"]).status()?;
+        assert!(status.success());
+        assert_eq!(fs::read(&received)?, bytes);
+        assert_eq!(fs::read(&tee)?, bytes);
+
+        // Only the stdin of the first command line, which reads <stdin>
+        let status = Command::new("./target/debug/o-o").args(["--stdin-tee", SU(&tee), SU(&input), SU(&received), "-", "cat", "J", "true"])
+            .stdin(std::process::Stdio::null()).status()?;
+        assert!(status.success());
+        assert_eq!(fs::read(&tee)?, bytes);
+
+        // Files concatenated by --glob
+        fs::write(temp_dir.path().join("part-1.txt"), "a\n")?;
+        fs::write(temp_dir.path().join("part-2.txt"), "b\n")?;
+        let pattern = temp_dir.path().join("part-*.txt");
+        let status = Command::new("./target/debug/o-o").args(["--glob", "--stdin-tee", SU(&tee), SU(&pattern), SU(&received), "-", "cat"]).status()?;
+        assert!(status.success());
+        assert_eq!(fs::read(&received)?, b"a\nb\n");
+        assert_eq!(fs::read(&tee)?, b"a\nb\n");
+
+        // The stdin of o-o, when <stdin> is `-`
+        let mut child = Command::new("./target/debug/o-o").args(["--stdin-tee", SU(&tee), "-", "-", "-", "wc", "-c"])
+            .stdin(std::process::Stdio::piped()).stdout(std::process::Stdio::piped()).spawn()?;
+        child.stdin.take().unwrap().write_all(b"hello\n")?;
+        let output = child.wait_with_output()?;
+        assert_eq!(String::from_utf8(output.stdout)?.trim(), "6");
+        assert_eq!(fs::read(&tee)?, b"hello\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn chdir_to_input() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let sub_dir = temp_dir.path().join("sub");
+        fs::create_dir(&sub_dir)?;
+        fs::write(sub_dir.join("input.txt"), "input\n")?;
+        fs::write(sub_dir.join("sibling.txt"), "sibling\n")?;
+        let o_o = std::fs::canonicalize("./target/debug/o-o")?;
+
+        // Relative to the current directory, <stdin> and <stdout> are opened by o-o, and `sibling.txt` by the command
+        let output = Command::new(&o_o).current_dir(temp_dir.path())
+            .args(["--chdir-to-input", "sub/input.txt", "out.txt", "-", "sh", "-c", "cat - sibling.txt; pwd"]).output()?;
+        assert!(output.status.success());
+        let sub_dir = std::fs::canonicalize(&sub_dir)?;
+        assert_eq!(fs::read_to_string(temp_dir.path().join("out.txt"))?, format!("input\nsibling\n{}\n", SU(&sub_dir)));
+
+        // -d wins
+        let output = Command::new(&o_o).current_dir(temp_dir.path())
+            .args(["--chdir-to-input", "-d", SU(temp_dir.path()), "sub/input.txt", "-", "-", "ls"]).output()?;
+        assert!(output.status.success());
+        assert!(String::from_utf8(output.stdout)?.lines().any(|line| line == "sub"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tee_stdout_and_stderr() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let stdout_file = temp_dir.path().join("out.txt");
+        let stderr_file = temp_dir.path().join("err.txt");
+
+        // Many lines on both streams, more than a pipe buffer, so that reading one of them only would block the command
+        let script = "for i in $(seq 1 20000); do echo out$i; echo err$i >&2; done";
+        let output = Command::new("./target/debug/o-o").args(["--tee", "-", SU(&stdout_file), SU(&stderr_file), "sh", "-c", script]).output()?;
+        assert!(output.status.success());
+        let expected = |name: &str| (1..=20000).map(|i| format!("{}{}\n", name, i)).collect::<String>();
+        assert_eq!(fs::read_to_string(&stdout_file)?, expected("out"));
+        assert_eq!(fs::read_to_string(&stderr_file)?, expected("err"));
+        assert_eq!(String::from_utf8(output.stdout)?, expected("out"));
+        assert_eq!(String::from_utf8(output.stderr)?, expected("err"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn fail_on_empty_output() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let input = temp_dir.path().join("input.txt");
+        fs::write(&input, "keep\n")?;
+
+        let output = Command::new("./target/debug/o-o").args(["--fail-on-empty-output", SU(&input), "=", "-", "true"]).output()?;
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8(output.stderr)?.contains("wrote nothing to stdout"));
+        assert_eq!(fs::read_to_string(&input)?, "keep\n");
+
+        // Also for `-`, and not for the output to stderr
+        let output = Command::new("./target/debug/o-o").args(["--fail-on-empty-output", "-", "-", "=", "sh", "-c", "echo x >&2"]).output()?;
+        assert_eq!(output.status.code(), Some(1));
+
+        let output = Command::new("./target/debug/o-o").args(["--fail-on-empty-output", "-", "-", "-", "echo", "x"]).output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"x\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn idle_timeout() -> anyhow::Result<()> {
+        // killed in the silence after the first line
+        let start = std::time::Instant::now();
+        let output = Command::new("./target/debug/o-o").args(["--idle-timeout", "0.5", "-", "-", "-", "sh", "-c", "echo first; sleep 5; echo second"]).output()?;
+        assert_eq!(output.status.code(), Some(124));
+        assert_eq!(output.stdout, b"first\n");
+        assert!(String::from_utf8(output.stderr)?.contains("wrote no output"));
+        assert!(start.elapsed() < Duration::from_secs(4));
+
+        // the output in time keeps it running
+        let output = Command::new("./target/debug/o-o").args(["--idle-timeout", "1", "-", "-", "-", "sh", "-c", "for i in 1 2 3 4; do echo $i; sleep 0.3; done"]).output()?;
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"1\n2\n3\n4\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn publish_as() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let data = temp_dir.path().join("data");
+        let done = temp_dir.path().join("data.done");
+
+        let status = Command::new("./target/debug/o-o").args(["--publish-as", SU(&done), "-", SU(&data), "-", "echo", "hello"]).status()?;
+        assert!(status.success());
+        assert!(!data.exists());
+        assert_eq!(fs::read_to_string(&done)?, "hello\n");
+
+        // A failure does not replace the published file
+        let status = Command::new("./target/debug/o-o").args(["--publish-as", SU(&done), "-", SU(&data), "-", "sh", "-c", "echo partial; exit 1"]).status()?;
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(fs::read_to_string(&data)?, "partial\n");
+        assert_eq!(fs::read_to_string(&done)?, "hello\n");
+
+        // Only the stdout file of the first command line is published, and the later ones are not redirected
+        let output = Command::new("./target/debug/o-o").args(["--publish-as", SU(&done), "-", SU(&data), "-", "echo", "a", "J", "echo", "b"]).output()?;
+        assert!(output.status.success());
+        assert!(!data.exists());
+        assert_eq!(fs::read_to_string(&done)?, "a\n");
+        assert_eq!(String::from_utf8(output.stdout)?, "b\n");
+
+        let output = Command::new("./target/debug/o-o").args(["--publish-as", SU(&done), "-", "-", "-", "true"]).output()?;
+        assert_eq!(output.status.code(), Some(2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn equal_as_stdin() -> anyhow::Result<()> {
+        let temp_dir = tempdir()?;
+        let file = temp_dir.path().join("file.txt");
+        fs::write(&file, "b\na\n")?;
+
+        // Same as `file = -`: the file named as stdout is read, and replaced with the output
+        let status = Command::new("./target/debug/o-o").args(["=", SU(&file), "-", "sort"]).status()?;
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(&file)?, "a\nb\n");
+
+        // Unlike a plain stdout file, which would be truncated before sort reads it, a failure leaves the file as is
+        let status = Command::new("./target/debug/o-o").args(["=", SU(&file), "-", "sh", "-c", "echo x; exit 1"]).status()?;
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(fs::read_to_string(&file)?, "a\nb\n");
+
+        let output = Command::new("./target/debug/o-o").args(["=", "-", "-", "cat"]).output()?;
+        assert_eq!(output.status.code(), Some(2));
+
+        Ok(())
+    }
 }
@@ -1,6 +1,5 @@
 #[cfg(test)]
 #[allow(non_snake_case)]
-
 mod executable_tests {
     use std::fs;
     use std::io;
@@ -11,13 +10,13 @@ mod executable_tests {
 
     use tempfile::tempdir;
 
-    fn SU<'a>(p: &'a Path) -> &'a str {
+    fn SU(p: &Path) -> &str {
         p.to_str().unwrap()
     }
 
     pub fn file_write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
         let mut f = fs::File::create(path)?;
-        f.write(contents.as_ref())?;
+        f.write_all(contents.as_ref())?;
         f.sync_all()?;
 
         Ok(())
@@ -40,12 +39,12 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "file a.\n")?;
+        file_write(SU(&file_a), "file a.\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let temp_file = temp_dir.path().join("ls-output.txt");
         let output = Command::new("./target/debug/o-o")
-            .args(["-d", SU(&temp_dir.path()), "-", SU(&temp_file), "-", "ls"])
+            .args(["-d", SU(temp_dir.path()), "-", SU(&temp_file), "-", "ls"])
             .output()?;
 
         assert_eq!(output.status.code().unwrap(), 0);
@@ -68,7 +67,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 "-",
@@ -90,7 +89,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
+        file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -98,7 +97,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "-",
                 "-",
@@ -123,7 +122,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script = temp_dir.path().join(SCRIPT);
-        let _ = file_write(SU(&script), "echo \"stdout\" >&1\necho \"stderr\" >&2\n")?;
+        file_write(SU(&script), "echo \"stdout\" >&1\necho \"stderr\" >&2\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let out_file = temp_dir.path().join("out.txt");
@@ -133,7 +132,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 SU(&out_file),
                 SU(&err_file),
@@ -163,7 +162,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script = temp_dir.path().join(SCRIPT);
-        let _ = file_write(SU(&script), "echo \"stdout\" >&1\necho \"stderr\" >&2\n")?;
+        file_write(SU(&script), "echo \"stdout\" >&1\necho \"stderr\" >&2\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -171,7 +170,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 "=",
@@ -203,7 +202,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 &append_out_file,
                 "-",
@@ -218,7 +217,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 &append_out_file,
                 "-",
@@ -243,11 +242,11 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "file a.\n")?;
+        file_write(SU(&file_a), "file a.\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let status = Command::new("cargo")
-            .args(["run", "--", "-d", SU(&temp_dir.path()), SU(&file_a), "=", "-", "wc"])
+            .args(["run", "--", "-d", SU(temp_dir.path()), SU(&file_a), "=", "-", "wc"])
             .status()?;
         assert!(status.code().unwrap() == 0);
 
@@ -265,7 +264,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
+        file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -273,7 +272,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-p",
                 "P",
                 "-",
@@ -304,7 +303,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
+        file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -312,7 +311,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-s",
                 "S",
                 "-",
@@ -344,7 +343,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
+        file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -352,7 +351,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-s",
                 "S",
                 "-",
@@ -382,7 +381,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
+        file_write(SU(&file_a), "1st line\n2nd line\n3rd line\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
         let file_b = temp_dir.path().join(FILE_A);
 
@@ -391,7 +390,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-s",
                 "S",
                 "-",
@@ -426,13 +425,13 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script_echo_and_fail = temp_dir.path().join(SCRIPT_ECHO_AND_FAIL);
-        let _ = file_write(
+        file_write(
             SU(&script_echo_and_fail),
             "#!/bin/bash\n\necho \"echo and fail!\"\nexit 12\n",
         )?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "file a original contents\n")?;
+        file_write(SU(&file_a), "file a original contents\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let status = Command::new("cargo")
@@ -440,7 +439,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "=",
                 "-",
@@ -452,7 +451,7 @@ mod executable_tests {
 
         let file_a_contents = fs::read_to_string(SU(&file_a))?;
         assert!(file_a_contents.find("original contents").is_some());
-        assert!(!file_a_contents.find("echo and fail!").is_some());
+        assert!(file_a_contents.find("echo and fail!").is_none());
 
         temp_dir.close()?;
         Ok(())
@@ -466,13 +465,13 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script_echo_and_fail = temp_dir.path().join(SCRIPT_ECHO_AND_FAIL);
-        let _ = file_write(
+        file_write(
             SU(&script_echo_and_fail),
             "#!/bin/bash\n\necho \"echo and fail!\"\nexit 12\n",
         )?;
 
         let file_a = temp_dir.path().join(FILE_A);
-        let _ = file_write(SU(&file_a), "file a original contents\n")?;
+        file_write(SU(&file_a), "file a original contents\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let status = Command::new("cargo")
@@ -481,7 +480,7 @@ mod executable_tests {
                 "--",
                 "-F",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 SU(&file_a),
                 "=",
                 "-",
@@ -492,7 +491,7 @@ mod executable_tests {
         assert!(status.code().unwrap() != 0);
 
         let file_a_contents = fs::read_to_string(SU(&file_a))?;
-        assert!(!file_a_contents.find("original contents").is_some());
+        assert!(file_a_contents.find("original contents").is_none());
         assert!(file_a_contents.find("echo and fail!").is_some());
 
         temp_dir.close()?;
@@ -506,7 +505,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script = temp_dir.path().join(SCRIPT);
-        let _ = file_write(SU(&script), "echo $V\n")?;
+        file_write(SU(&script), "echo $V\n")?;
         yield_now(); // force occurs a context switch, with hoping to complete file IOs
 
         let output = Command::new("cargo")
@@ -514,7 +513,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-e",
                 "V=some",
                 "-",
@@ -539,13 +538,13 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let output = Command::new("cargo")
-            .args(["run", "--", "-d", SU(&temp_dir.path()), "-", ".", "-", "echo", "hello"])
+            .args(["run", "--", "-d", SU(temp_dir.path()), "-", ".", "-", "echo", "hello"])
             .output()?;
 
         assert!(output.status.code().unwrap() == 0);
 
         let output_contents = String::from_utf8(output.stdout).unwrap();
-        assert!(!output_contents.find("hello").is_some());
+        assert!(output_contents.find("hello").is_none());
 
         temp_dir.close()?;
         Ok(())
@@ -558,7 +557,7 @@ mod executable_tests {
         let temp_dir = tempdir()?;
 
         let script = temp_dir.path().join(SCRIPT);
-        let _ = file_write(
+        file_write(
             SU(&script),
             "echo !!If you see this message, the test \"stderr_devnull\" failed.!! >&2\n",
         )?;
@@ -569,7 +568,7 @@ mod executable_tests {
                 "run",
                 "--",
                 "-d",
-                SU(&temp_dir.path()),
+                SU(temp_dir.path()),
                 "-",
                 "-",
                 ".",
@@ -1,8 +1,12 @@
 #[cfg(test)]
 #[allow(non_snake_case)]
-
 #[cfg(test)]
 mod func_tests {
+    use std::fs;
+    use std::process::Command;
+
+    use tempfile::tempdir;
+
     use o_o::*;
 
     #[test]
@@ -16,4 +20,480 @@ mod func_tests {
         let h4_command_exists = command_exists("hoge-hoge-hoge-hoge");
         assert!(!h4_command_exists);
     }
+
+    #[test]
+    fn resolve_envs_last_wins() {
+        let envs = vec![("A", Some("1")), ("B", Some("2")), ("A", Some("3"))];
+        assert_eq!(resolve_envs(&envs), vec![("A", Some("3")), ("B", Some("2"))]);
+    }
+
+    #[test]
+    fn resolve_envs_sub_command_overrides_parent() {
+        let parent = vec![("V", Some("parent")), ("W", Some("w"))];
+        let sub = vec![("V", Some("sub")), ("V", Some("sub2"))];
+        let mut envs = parent.clone();
+        envs.extend_from_slice(&sub);
+        assert_eq!(resolve_envs(&envs), vec![("V", Some("sub2")), ("W", Some("w"))]);
+    }
+
+    #[test]
+    fn resolve_envs_unset_vs_empty() {
+        let envs = vec![("V", Some("x")), ("V", None)];
+        assert_eq!(resolve_envs(&envs), vec![("V", None)]);
+
+        let envs = vec![("V", None), ("V", Some(""))];
+        assert_eq!(resolve_envs(&envs), vec![("V", Some(""))]);
+    }
+
+    fn spec_of(commands: &[&[&str]]) -> PipelineSpec {
+        PipelineSpec {
+            commands: commands.iter().map(|c| c.iter().map(|a| a.to_string()).collect()).collect(),
+            fds: vec!["-".to_string(), "-".to_string(), "-".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn run_streaming_reassembles_output() {
+        let spec = spec_of(&[&["sh", "-c", "for i in 1 2 3; do echo out$i; echo err$i >&2; done"]]);
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let code = run_streaming(&spec, |c| { out.extend_from_slice(c); Ok(()) }, |c| { err.extend_from_slice(c); Ok(()) }).unwrap();
+
+        assert_eq!(code, 0);
+        assert_eq!(String::from_utf8(out).unwrap(), "out1\nout2\nout3\n");
+        assert_eq!(String::from_utf8(err).unwrap(), "err1\nerr2\nerr3\n");
+    }
+
+    #[test]
+    fn run_streaming_callback_error_propagates() {
+        let spec = spec_of(&[&["sh", "-c", "echo hello; sleep 5"]]);
+        let r = run_streaming(&spec, |_| Err(anyhow::anyhow!("stop")), |_| Ok(()));
+
+        assert_eq!(r.unwrap_err().to_string(), "stop");
+    }
+
+    #[test]
+    fn builder_two_stage_pipe_matches_cli() {
+        let temp_dir = tempdir().unwrap();
+        let input = temp_dir.path().join("in.txt");
+        fs::write(&input, "b\na\nb\n").unwrap();
+        let input = input.to_str().unwrap();
+        let out_builder = temp_dir.path().join("out-builder.txt");
+        let out_cli = temp_dir.path().join("out-cli.txt");
+
+        let code = PipelineBuilder::new()
+            .stdin(input)
+            .stdout(out_builder.to_str().unwrap())
+            .command(["sort"])
+            .pipe(["uniq", "-c"])
+            .run()
+            .unwrap();
+        assert_eq!(code, 0);
+
+        let status = Command::new("./target/debug/o-o")
+            .args([input, out_cli.to_str().unwrap(), "-", "sort", "I", "uniq", "-c"])
+            .status()
+            .unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+
+        assert_eq!(fs::read_to_string(&out_builder).unwrap(), fs::read_to_string(&out_cli).unwrap());
+    }
+
+    #[test]
+    fn builder_overwrite_matches_cli() {
+        let temp_dir = tempdir().unwrap();
+        let file_builder = temp_dir.path().join("builder.txt");
+        let file_cli = temp_dir.path().join("cli.txt");
+        fs::write(&file_builder, "1st line\n2nd line\n").unwrap();
+        fs::write(&file_cli, "1st line\n2nd line\n").unwrap();
+
+        let code = PipelineBuilder::new()
+            .stdin(file_builder.to_str().unwrap())
+            .stdout_overwrite()
+            .command(["wc", "-l"])
+            .env("LC_ALL", "C")
+            .run()
+            .unwrap();
+        assert_eq!(code, 0);
+
+        let status = Command::new("./target/debug/o-o")
+            .args(["-e", "LC_ALL=C", file_cli.to_str().unwrap(), "=", "-", "wc", "-l"])
+            .status()
+            .unwrap();
+        assert_eq!(status.code().unwrap(), 0);
+
+        assert_eq!(fs::read_to_string(&file_builder).unwrap().trim(), "2");
+        assert_eq!(fs::read_to_string(&file_builder).unwrap(), fs::read_to_string(&file_cli).unwrap());
+    }
+
+    #[test]
+    fn builder_rejects_invalid_fds() {
+        let r = PipelineBuilder::new().stdout_overwrite().force_overwrite(true).command(["cat"]).build();
+        assert!(r.is_err());
+
+        let r = PipelineBuilder::new().stdin("a").stdout("a").command(["cat"]).build();
+        assert!(r.is_err());
+
+        let r = PipelineBuilder::new().build();
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn parse_pipelines_nested() {
+        let tokens = vec!["cat", "a", "I", "wc", "-l", "J", "echo", "x", "J", "ls", "I", "sort", "I", "head"];
+        let pls = parse_pipelines(&tokens, "I", "J").unwrap();
+
+        assert_eq!(pls, vec![
+            vec![vec!["cat", "a"], vec!["wc", "-l"]],
+            vec![vec!["echo", "x"]],
+            vec![vec!["ls"], vec!["sort"], vec!["head"]],
+        ]);
+    }
+
+    #[test]
+    fn parse_pipelines_disabled_tokens() {
+        let tokens = vec!["echo", "I", "J"];
+        let pls = parse_pipelines(&tokens, "", "").unwrap();
+
+        assert_eq!(pls, vec![vec![vec!["echo", "I", "J"]]]);
+    }
+
+    #[test]
+    fn parse_pipelines_empty_segments() {
+        for tokens in [
+            vec!["J", "echo"],
+            vec!["echo", "J"],
+            vec!["echo", "J", "J", "ls"],
+            vec!["I", "echo"],
+            vec!["echo", "I"],
+            vec!["echo", "I", "J", "ls"],
+            vec![],
+        ] {
+            assert!(parse_pipelines(&tokens, "I", "J").is_err(), "{:?}", tokens);
+        }
+    }
+
+    #[test]
+    fn strip_ansi_colors_and_cursor_moves() {
+        let mut stripper = AnsiStripper::new();
+        assert_eq!(stripper.strip(b"\x1b[1;31mred\x1b[0m plain"), b"red plain");
+        assert_eq!(stripper.strip(b"a\x1b[2Kb\x1b[10;20Hc\x1b[?25ld"), b"abcd");
+        assert_eq!(stripper.strip(b"\x1b]0;title\x07e\x1b]8;;url\x1b\\f"), b"ef");
+        assert_eq!(stripper.strip(b"\x1b(Bg\x1b7h"), b"gh");
+        assert_eq!(stripper.strip("日本語\n".as_bytes()), "日本語\n".as_bytes());
+    }
+
+    #[test]
+    fn strip_ansi_sequence_split_across_chunks() {
+        let mut stripper = AnsiStripper::new();
+        let mut out = vec![];
+        for chunk in [&b"x\x1b"[..], b"[3", b"8;5;2", b"08my\x1b", b"[0", b"m\n"] {
+            out.extend(stripper.strip(chunk));
+        }
+        assert_eq!(out, b"xy\n");
+    }
+
+    #[test]
+    fn line_ending_conversion() {
+        let input: &[u8] = b"a\nb\r\nc\rd\n";
+        assert_eq!(LineEndingConverter::new(LineEnding::Lf).convert(input), b"a\nb\nc\rd\n");
+        assert_eq!(LineEndingConverter::new(LineEnding::Crlf).convert(input), b"a\r\nb\r\nc\rd\r\n");
+    }
+
+    #[test]
+    fn line_ending_conversion_split_across_chunks() {
+        let mut converter = LineEndingConverter::new(LineEnding::Lf);
+        let mut out = vec![];
+        for chunk in [&b"a\r"[..], b"\nb\r", b"c\r"] {
+            out.extend(converter.convert(chunk));
+        }
+        assert_eq!(out, b"a\nb\rc");
+    }
+
+    #[test]
+    fn validate_working_directory_accepts_directory() {
+        let temp_dir = tempdir().unwrap();
+        assert!(validate_working_directory(temp_dir.path().to_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn validate_working_directory_rejects_missing_or_file() {
+        let temp_dir = tempdir().unwrap();
+        let missing = temp_dir.path().join("missing");
+        let file = temp_dir.path().join("file.txt");
+        fs::write(&file, "").unwrap();
+
+        match validate_working_directory(missing.to_str().unwrap()) {
+            Err(OOError::WorkingDirectory { reason, .. }) => assert_eq!(reason, "no such directory"),
+            r => panic!("unexpected: {:?}", r),
+        }
+        match validate_working_directory(file.to_str().unwrap()) {
+            Err(OOError::WorkingDirectory { reason, .. }) => assert_eq!(reason, "not a directory"),
+            r => panic!("unexpected: {:?}", r),
+        }
+    }
+
+    #[test]
+    fn format_pipeline_quotes_special_arguments() {
+        let commands = vec![
+            vec!["grep".to_string(), "a b".to_string(), "it's".to_string()],
+            vec!["wc".to_string(), "-l".to_string(), "".to_string()],
+        ];
+        assert_eq!(format_pipeline(&commands), "grep 'a b' 'it'\\''s' | wc -l ''");
+    }
+
+    #[test]
+    fn normalize_exit_code_range() {
+        assert_eq!(normalize_exit_code(0), 0);
+        assert_eq!(normalize_exit_code(3), 3);
+        assert_eq!(normalize_exit_code(255), 255);
+        assert_eq!(normalize_exit_code(256), 255);
+        assert_eq!(normalize_exit_code(-1), 255);
+        assert_eq!(normalize_exit_code(0xC0000005_u32 as i32), 255);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn exit_code_of_killed_process() {
+        let spec = PipelineSpec {
+            commands: vec![vec!["sh".to_string(), "-c".to_string(), "kill -TERM $$".to_string()]],
+            fds: vec!["-".to_string(), "-".to_string(), "-".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(run_pipeline(&spec).unwrap(), 128 + 15);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn stage_exit_codes_of_pipe() {
+        let spec = PipelineSpec {
+            commands: vec![
+                vec!["echo".to_string(), "a".to_string()],
+                vec!["sh".to_string(), "-c".to_string(), "cat > /dev/null; exit 3".to_string()],
+                vec!["cat".to_string()],
+            ],
+            fds: vec!["-".to_string(), ".".to_string(), "-".to_string()],
+            ..Default::default()
+        };
+        let outcome = run_pipeline_outcome(&spec).unwrap();
+        assert_eq!(outcome.exit_code, 3);
+        assert_eq!(outcome.stage_exit_codes, vec![Some(0), Some(3), Some(0)]);
+
+        // the same when o-o reads the output of the pipeline
+        let spec = PipelineSpec { stdout_hash: Some(HashAlgorithm::Md5), ..spec };
+        assert_eq!(run_pipeline_outcome(&spec).unwrap().stage_exit_codes, vec![Some(0), Some(3), Some(0)]);
+    }
+
+    #[test]
+    fn parse_pipelines_allowing_empty_segments() {
+        let options = ParseOptions { allow_empty_segments: true, ..Default::default() };
+        let expected = vec![vec![vec!["a".to_string()]], vec![vec!["b".to_string()]]];
+        assert_eq!(parse_pipelines_with_options(&["J", "a", "J", "b"], "I", "J", &options).unwrap(), expected);
+        assert_eq!(parse_pipelines_with_options(&["a", "J", "b", "J"], "I", "J", &options).unwrap(), expected);
+        assert_eq!(parse_pipelines_with_options(&["a", "J", "J", "b"], "I", "J", &options).unwrap(), expected);
+        assert!(parse_pipelines_with_options(&["J", "J"], "I", "J", &options).is_err());
+        assert!(parse_pipelines_with_options(&["a", "I", "J", "b"], "I", "J", &options).is_err());
+
+        // strict by default
+        assert!(parse_pipelines(&["J", "a"], "I", "J").is_err());
+        assert!(parse_pipelines(&["a", "J", "J", "b"], "I", "J").is_err());
+    }
+
+    #[test]
+    fn parse_pipelines_with_comments() {
+        let options = ParseOptions { comment: Some("#".to_string()), ..Default::default() };
+        let expected = vec![vec![vec!["a".to_string()]], vec![vec!["b".to_string()]]];
+        assert_eq!(parse_pipelines_with_options(&["#", "note", "I", "x", "J", "a", "J", "b"], "I", "J", &options).unwrap(), expected);
+        assert_eq!(parse_pipelines_with_options(&["a", "#", "note", "I", "x", "J", "b", "J", "#", "end"], "I", "J", &options).unwrap(), expected);
+        assert!(parse_pipelines_with_options(&["#", "only"], "I", "J", &options).is_err());
+        assert!(parse_pipelines(&["a", "#", "b"], "I", "J").unwrap()[0][0].contains(&"#".to_string()));
+    }
+
+    #[test]
+    fn open_file_with_mode_reports_mode() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("out.txt");
+        let path = path.to_str().unwrap();
+        fs::write(path, "old\n").unwrap();
+
+        let (_, mode) = open_file_with_mode(&format!("+{}", path)).unwrap();
+        assert_eq!(mode, OpenMode::Append);
+        assert_eq!(fs::read_to_string(path).unwrap(), "old\n");
+
+        let (_, mode) = open_file_with_mode(path).unwrap();
+        assert_eq!(mode, OpenMode::Truncate);
+        assert_eq!(fs::read_to_string(path).unwrap(), "");
+    }
+
+    #[test]
+    fn retry_jitter_stays_within_bounds() {
+        use std::time::Duration;
+
+        let mut rng = Rng::with_seed(12345);
+        let delay = Duration::from_millis(100);
+        let jitter = Duration::from_millis(30);
+        let delays: Vec<Duration> = (0..100).map(|_| jittered_delay(delay, jitter, &mut rng)).collect();
+        assert!(delays.iter().all(|&d| Duration::from_millis(70) <= d && d <= Duration::from_millis(130)));
+        assert!(delays.iter().any(|&d| d != delays[0]));
+
+        // not going below zero
+        let mut rng = Rng::from_process();
+        assert!((0..100).all(|_| jittered_delay(Duration::from_millis(10), jitter, &mut rng) <= Duration::from_millis(40)));
+        assert_eq!(jittered_delay(delay, Duration::ZERO, &mut rng), delay);
+    }
+
+    #[test]
+    fn jail_working_directory_of_chroot() {
+        use std::path::{Path, PathBuf};
+
+        assert_eq!(jail_working_directory(None), PathBuf::from("/"));
+        assert_eq!(jail_working_directory(Some(Path::new("work/a"))), PathBuf::from("/work/a"));
+        assert_eq!(jail_working_directory(Some(Path::new("/work"))), PathBuf::from("/work"));
+    }
+
+    #[test]
+    fn env_files_in_dotenv_and_json() {
+        let dotenv = "# comment\n\nexport A=plain value \nB=\"two\\nlines \\\"quoted\\\"\" # comment\nC='single \\n'\nD=\n";
+        let json = "{\n  \"A\": \"plain value\",\n  \"B\": \"two\\nlines \\\"quoted\\\"\",\n  \"C\": \"single \\\\n\",\n  \"D\": \"\"\n}\n";
+        let expected: Vec<(String, String)> = [("A", "plain value"), ("B", "two\nlines \"quoted\""), ("C", "single \\n"), ("D", "")]
+            .iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(parse_dotenv(dotenv).unwrap(), expected);
+        assert_eq!(parse_json_env(json).unwrap(), expected);
+    }
+
+    #[test]
+    fn env_file_errors_with_position() {
+        assert_eq!(parse_dotenv("A=1\nB=\"open\n").unwrap_err(), "line 2, column 3: unterminated quoted value");
+        assert_eq!(parse_dotenv("A=1\n  no equal\n").unwrap_err(), "line 2, column 3: expected `KEY=VALUE`");
+        assert_eq!(parse_dotenv("1A=x\n").unwrap_err(), "line 1, column 1: invalid variable name: 1A");
+        assert_eq!(parse_dotenv("A=\"x\" y\n").unwrap_err(), "line 1, column 7: unexpected characters after the quoted value");
+        assert_eq!(parse_json_env("{\n  \"A\": 1\n}").unwrap_err(), "line 2, column 8: invalid type: integer `1`, expected a string");
+        assert_eq!(parse_json_env("{\"A\": \"x\"").unwrap_err(), "line 1, column 9: EOF while parsing an object");
+        assert_eq!(parse_json_env("{\"A\": \"\\u+041\"}").unwrap_err(), "line 1, column 13: invalid escape");
+    }
+
+    #[test]
+    fn temp_file_with_prefix_and_suffix() {
+        let dir = tempdir().unwrap();
+        let path = create_temp_file(&dir.path().to_str(), Some("oo-"), Some(".json")).unwrap();
+        assert_eq!(path.parent().unwrap(), dir.path());
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("oo-") && name.ends_with(".json") && name.len() > "oo-.json".len(), "{}", name);
+
+        let path = create_temp_file(&dir.path().to_str(), None, None).unwrap();
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with("tempfile"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn parse_signal_names_and_numbers() {
+        assert_eq!(parse_signal("TERM"), Some(15));
+        assert_eq!(parse_signal("sigterm"), Some(15));
+        assert_eq!(parse_signal("HUP"), Some(1));
+        assert_eq!(parse_signal("9"), Some(9));
+        assert_eq!(parse_signal("KILL"), Some(9));
+        assert_eq!(parse_signal("NOSUCH"), None);
+        assert_eq!(parse_signal("0"), None);
+        assert_eq!(parse_signal("-1"), None);
+    }
+
+    #[test]
+    fn parse_octal_modes() {
+        assert_eq!(parse_octal_mode("644").unwrap(), 0o644);
+        assert_eq!(parse_octal_mode("0644").unwrap(), 0o644);
+        assert_eq!(parse_octal_mode("0o644").unwrap(), 0o644);
+        assert_eq!(parse_octal_mode("0").unwrap(), 0);
+        assert_eq!(parse_octal_mode("4755").unwrap(), 0o4755);
+        assert_eq!(parse_octal_mode("00755").unwrap(), 0o755);
+        for invalid in ["", "0o", "999", "abc", "0x644", "64a", "-644", "+644", "17777", " 644"] {
+            assert!(parse_octal_mode(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn parse_pipelines_with_token_regex() {
+        let options = ParseOptions { token_regex: true, ..Default::default() };
+        let tokens = vec!["cat", "a.txt", "I", "sort", "|", "uniq", ";", "echo", "done"];
+        let pipelines = parse_pipelines_with_options(&tokens, "I|\\|", "J|;", &options).unwrap();
+        assert_eq!(pipelines, vec![vec![vec!["cat", "a.txt"], vec!["sort"], vec!["uniq"]], vec![vec!["echo", "done"]]]);
+
+        // a token has to match the pattern as a whole
+        let tokens = vec!["echo", "II", "I", "cat"];
+        let pipelines = parse_pipelines_with_options(&tokens, "I", "J", &options).unwrap();
+        assert_eq!(pipelines, vec![vec![vec!["echo", "II"], vec!["cat"]]]);
+
+        // exact matching by default
+        let pipelines = parse_pipelines_with_options(&["echo", "I|\\|", "x"], "I|\\|", "J", &ParseOptions::default()).unwrap();
+        assert_eq!(pipelines, vec![vec![vec!["echo"], vec!["x"]]]);
+
+        assert!(parse_pipelines_with_options(&["echo"], "(", "J", &options).is_err());
+    }
+
+    #[test]
+    fn json_array_into_ndjson() {
+        let content = "[\n  {\"a\": 1, \"b\": [true, null]},\n  {\"text\": \"x y\\n\\\"z\\\"\"},\n  -1.5e3\n]\n";
+        assert_eq!(json_array_to_ndjson(content).unwrap(), vec![
+            "{\"a\":1,\"b\":[true,null]}".to_string(),
+            "{\"text\":\"x y\\n\\\"z\\\"\"}".to_string(),
+            "-1.5e+3".to_string(),
+        ]);
+        assert_eq!(json_array_to_ndjson("[]").unwrap(), Vec::<String>::new());
+
+        assert_eq!(json_array_to_ndjson("{\"a\": 1}").unwrap_err(), "line 1, column 0: invalid type: map, expected a sequence");
+        assert_eq!(json_array_to_ndjson("[\n  {\"a\": 01}\n]").unwrap_err(), "line 2, column 10: invalid number");
+        assert_eq!(json_array_to_ndjson("[1, 2").unwrap_err(), "line 1, column 5: EOF while parsing a list");
+        assert_eq!(json_array_to_ndjson("[1] x").unwrap_err(), "line 1, column 5: trailing characters");
+    }
+
+    #[test]
+    fn validate_command_lines() {
+        let message = |argv: &[&str]| validate(argv).unwrap_err().to_string();
+
+        assert!(validate(&["o-o", "-", "-", "-", "cat", "a.txt", "I", "wc"]).is_ok());
+        assert!(validate(&["o-o", "in.txt", "=", "-", "sort"]).is_ok());
+        assert!(validate(&["o-o", "--help"]).is_ok());
+
+        // arguments and fds
+        assert_eq!(message(&["o-o", "in.txt"]), "o-o: no command line specified");
+        assert!(message(&["o-o", "-d"]).contains("-d"));
+        assert!(message(&["o-o", "a.txt", "a.txt", "-", "cat"]).starts_with("o-o: <stdin> and <stdout> are the same file: a.txt"));
+        assert_eq!(message(&["o-o", "=", "-", "-", "cat"]), "o-o: `=` as stdin requires a file name for <stdout>, without `+`: -");
+        assert_eq!(message(&["o-o", "-F", "-", "=", "-", "cat"]), "o-o: option --force-overwrite requires a real file name");
+
+        // pipe and separators
+        assert_eq!(message(&["o-o", "-s", "I", "-", "-", "-", "cat"]), "o-o: options --pipe and --separator have the same argument: I");
+        assert_eq!(message(&["o-o", "--and-separator", "J", "-", "-", "-", "cat"]), "o-o: options --separator and --and-separator have the same argument: J");
+        assert_eq!(message(&["o-o", "-p", "a b", "-", "-", "-", "cat"]), "o-o: option --pipe's argument should not contain whitespace: \"a b\"");
+
+        // command lines
+        assert_eq!(message(&["o-o", "-", "-", "-", "cat", "J"]), "o-o: empty command line (unexpected separator)");
+        assert_eq!(message(&["o-o", "-", "-", "-", "cat", "I", "I", "wc"]), "o-o: empty command line (unexpected pipe)");
+        assert!(validate(&["o-o", "--and-separator", "AND", "-", "-", "-", "true", "AND"]).is_err());
+
+        // options conflicting with the fds
+        assert_eq!(message(&["o-o", "--atomic-stdout", "-", "-", "-", "cat"]), "o-o: option --atomic-stdout requires a file name for <stdout>: -");
+        assert_eq!(message(&["o-o", "--publish-as=b.txt", "a.txt", "+out.txt", "-", "cat"]), "o-o: option --publish-as cannot be used with the append prefix `+`: +out.txt");
+        assert_eq!(message(&["o-o", "--glob", "*.txt", "=", "-", "cat"]), "o-o: option --glob can not be used with `=` as stdout when <stdin> is a pattern");
+        assert_eq!(message(&["o-o", "--stdout-on-error", "a.txt", "=", "-", "cat"]), "o-o: option --stdout-on-error can not be used with `=` as stdout");
+        assert_eq!(message(&["o-o", "--stdout-fd=3", "-", "out.txt", "-", "cat"]), "o-o: option --stdout-fd requires `-` as stdout: out.txt");
+        assert_eq!(message(&["o-o", "--stdin-json=a.json", "in.txt", "-", "-", "cat"]), "o-o: option --stdin-json cannot be used with a stdin file or --stdin-null");
+        assert_eq!(message(&["o-o", "--stdin-null", "in.txt", "-", "-", "cat"]), "o-o: option --stdin-null cannot be used with a stdin file: in.txt");
+
+        // options conflicting with the command lines
+        assert_eq!(message(&["o-o", "--capture=V:vars.sh", "-", "-", "-", "echo", "I", "cat"]), "o-o: option --capture cannot be used with a pipe or multiple command lines");
+        assert_eq!(message(&["o-o", "--capture=V:vars.sh", "-", "out.txt", "-", "echo"]), "o-o: option --capture cannot be used with a redirection of <stdout> or <stderr>");
+        assert!(validate(&["o-o", "--capture=V:vars.sh", "-", "=", "-", "echo"]).is_ok());
+        assert_eq!(message(&["o-o", "--max-parallel=2", "-", "-", "-", "true", "J", "true"]), "o-o: option --max-parallel requires --keep-going");
+        assert_eq!(message(&["o-o", "--max-parallel=2", "-k", "--and-separator=AND", "-", "-", "-", "true", "AND", "true"]), "o-o: option --max-parallel cannot be used with the and-separator or or-separator");
+        assert_eq!(message(&["o-o", "--max-parallel=2", "-k", "-", "a.txt", "-", "echo", "J", "o-o", "a.txt", "b.txt", "-", "cat"]), "o-o: command lines run in parallel share a file: a.txt");
+        assert!(validate(&["o-o", "--max-parallel=2", "-k", "-", "a.txt", "-", "echo", "J", "o-o", "-", "b.txt", "-", "echo"]).is_ok());
+
+        // o-o commands as the second or later command lines
+        assert_eq!(message(&["o-o", "-", "-", "-", "true", "J", "o-o", "-p", "P", "-", "-", "-", "true"]), "o-o: invalid option used in sub-command: --pipe");
+        assert!(message(&["o-o", "-", "-", "-", "true", "J", "o-o", "a.txt", "a.txt", "-", "cat"]).starts_with("o-o: <stdin> and <stdout> are the same file: a.txt"));
+
+        // nothing is run, even with the files not existing
+        assert!(validate(&["o-o", "no-such-file.txt", "out.txt", "-", "no-such-command"]).is_ok());
+    }
 }